@@ -1,10 +1,13 @@
 use std::net::SocketAddr;
+use std::sync::mpsc;
+use std::thread;
 
 use camino::Utf8PathBuf;
 use simple_server::Server;
 use simple_server::http::{Method, Request};
 use simple_server::helpers::{get_domain_certs, get_private_key, get_ssl_config};
-// use simple_server::websocket::{Message, WebSocket, WebSocketError};
+use simple_server::server_G::Notification;
+use simple_server::websocket::{WsHandshakeConfig, WsServer};
 
 fn main() {
     let domain_cert = get_domain_certs("https_certificates/domain.cert.pem");
@@ -15,10 +18,24 @@ fn main() {
     let mut server = Server::new(SocketAddr::from(([127, 0, 0, 1], 8783)), config);
 
     server.http.set_client_directory("fake_server/src");
-    server.http.add_service("/", Method::GET, serve_client_directory());
+    server.http.add_service("/*path", Method::GET, serve_client_directory());
     server.http.set_homepage("index.html");
     server.http.set_404_page("missing.html");
-    // server.set_websocket_handler(handle_websocket);
+
+    // the websocket server runs on its own thread and gets its clients from the http server's port
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut websockets = WsServer::detached();
+        sender.send(websockets.adopter()).unwrap();
+        loop {
+            if let Notification::SentMessage(id, message) = websockets.serve() {
+                if let Err(e) = websockets.send_message(id, message) {
+                    println!("couldn't echo to client {id}: {e}")
+                }
+            }
+        }
+    });
+    server.http.add_websocket("/ws", receiver.recv().unwrap(), WsHandshakeConfig::default());
 
     server.serve();
 
@@ -29,29 +46,3 @@ fn serve_client_directory() -> impl FnMut(Request) -> Utf8PathBuf {
         request.path
     }
 }
-
-// fn handle_websocket(mut socket: WebSocket) {
-//     loop {
-//         let start = Instant::now();
-//         match socket.read_message() {
-//             Ok(message) => {
-//                 match message {
-//                     (client, Message::Binary(bytes)) => {
-//                         println!("WEBSOCKET_MESSAGE_BINARY: {{{:?}}}", bytes);
-//                         socket.send_binary(&bytes, client).unwrap();
-//                     }
-//                     (client, Message::Text(text)) => {
-//                         println!("WEBSOCKET_MESSAGE_TEXT: {text}");
-//                         println!("WEBSOCKET_MESSAGE_LENGTH: {}", text.len());
-//                         socket.send_text(&text, client).unwrap();
-//                     }
-//                 }
-//             },
-//             Err(WebSocketError::WOULD_BLOCK) => {},
-//             Err(e) => panic!("{e}"),
-//         }
-//         let sleepy_time = Duration::from_millis(10).checked_sub(start.elapsed()).unwrap_or(Duration::from_millis(1));
-//         sleep(sleepy_time);
-//     }
-// }
-
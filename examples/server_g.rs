@@ -1,8 +1,9 @@
+use std::io::{self, Read};
 use std::net::SocketAddr;
 use std::time::Duration;
 
 use simple_server::server_G::Notification;
-use simple_server::websocket::WsServer;
+use simple_server::websocket::{CloseFrame, Message, WsServer};
 use simple_server::helpers::{get_domain_certs, get_private_key, get_ssl_config};
 
 fn main() {
@@ -13,16 +14,52 @@ fn main() {
     
     let mut server = WsServer::new(SocketAddr::from(([127, 0, 0, 1], 8782)), config);
     server.heartbeat = Some(Duration::from_millis(500));
+    server.close_timeout = Duration::from_secs(2);
+    server.session.ping_interval = Some(Duration::from_secs(1));
+    server.handshake.protocols = vec!["chat".to_owned()];
 
     let mut dots = 0;
     loop {
         match server.serve() {
+            Notification::Connected(id) => {
+                println!("client {id} connected speaking {:?}", server.protocol(id))
+            }
+            Notification::SentMessage(id, Message::Text(text)) if text == "close" => {
+                server.close(id, CloseFrame::NORMAL, "as requested");
+            }
+            Notification::SentMessage(id, Message::Text(text)) if text.starts_with('/') => {
+                // "/join room", "/room room text", "/all text" or "/stream size"
+                let (command, rest) = text.split_once(' ').unwrap_or((&text, ""));
+                let failed = match command {
+                    "/join" => { server.join(id, rest); Vec::new() },
+                    "/room" => {
+                        let (room, text) = rest.split_once(' ').unwrap_or((rest, ""));
+                        server.send_to_room(room, text.to_owned())
+                    },
+                    "/all" => server.broadcast(rest.to_owned()),
+                    "/stream" => {
+                        // "/stream 1000000" sends that many bytes without holding them in memory
+                        let size = rest.parse().unwrap_or(0);
+                        let source = io::repeat(b'x').take(size);
+                        server.send_stream(id, source, false).err().map(|e| (id, e)).into_iter().collect()
+                    },
+                    _ => Vec::new(),
+                };
+                for (id, e) in failed {
+                    println!("couldn't send to client {id}: {e}")
+                }
+            }
             Notification::SentMessage(id, message) => {
-                println!("client {id} sent:");
+                println!("client {id} sent (latency {:?}):", server.latency(id));
                 println!("{message:#?}");
-                server.send_message(id, message);
+                if let Err(e) = server.send_message(id, message) {
+                    println!("couldn't echo to client {id}: {e}")
+                }
+            }
+            Notification::Disconnected(id, Some(Message::Close(frame))) => {
+                println!("client {id} disconnected with {}: {}", frame.code, frame.reason)
             }
-            Notification::Disconnected(id) => {
+            Notification::Disconnected(id, _) => {
                 println!("client {id} disconnected")
             }
             Notification::Heartbeat => {
@@ -31,7 +68,7 @@ fn main() {
                 }
                 println!();
                 dots += 1;
-                dots = dots % 4;
+                dots %= 4;
             }
         }
     }
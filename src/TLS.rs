@@ -5,22 +5,36 @@ use rustls::{ServerConnection, ServerConfig};
 
 pub struct TLStream {
     pub tcp: TcpStream,
-    pub tls: ServerConnection,
+    /// None for plain tcp connections
+    pub tls: Option<ServerConnection>,
 }
 
 impl TLStream {
     pub fn new(tcp: TcpStream, config: Arc<ServerConfig>) -> Self {
         Self {
             tcp, 
-            tls: ServerConnection::new(config).unwrap() 
+            tls: Some(ServerConnection::new(config).unwrap()),
         }
     }
+    pub fn plain(tcp: TcpStream) -> Self {
+        Self {
+            tcp,
+            tls: None,
+        }
+    }
+    pub fn is_plain(&self) -> bool {
+        self.tls.is_none()
+    }
+    pub fn is_handshaking(&self) -> bool {
+        self.tls.as_ref().is_some_and(|tls| tls.is_handshaking())
+    }
     pub fn handshake(&mut self) -> Result<(), io::Error> {
+        let Some(tls) = &mut self.tls else { return Ok(()) };
         let mut eof = false;
         loop {
-            let until_handshaked = self.tls.is_handshaking();
-            while self.tls.wants_write() {
-                if self.tls.write_tls(&mut self.tcp)? == 0 {
+            let until_handshaked = tls.is_handshaking();
+            while tls.wants_write() {
+                if tls.write_tls(&mut self.tcp)? == 0 {
                     // EOF
                     self.tcp.flush()?;
                     return Ok(())
@@ -32,15 +46,15 @@ impl TLStream {
                 return Ok(());
             }
 
-            while !eof && self.tls.wants_read() {
-                match self.tls.read_tls(&mut self.tcp) {
+            while !eof && tls.wants_read() {
+                match tls.read_tls(&mut self.tcp) {
                     Ok(0) => eof = true,
                     Ok(_) => break,
                     Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {},
                     Err(err) => return Err(err),
                 };
             }
-            match self.tls.process_new_packets() {
+            match tls.process_new_packets() {
                 Ok(_) => {},
                 Err(e) => {
                     println!("rustls error: {e}");
@@ -48,7 +62,7 @@ impl TLStream {
                 },
             };
 
-            if until_handshaked && !self.tls.is_handshaking() && self.tls.wants_write() {
+            if until_handshaked && !tls.is_handshaking() && tls.wants_write() {
                 continue
             }
             if eof {
@@ -60,8 +74,9 @@ impl TLStream {
 
 impl Write for TLStream {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        while self.tls.wants_write() {
-            match self.tls.write_tls(&mut self.tcp) {
+        let Some(tls) = &mut self.tls else { return self.tcp.write(buf) };
+        while tls.wants_write() {
+            match tls.write_tls(&mut self.tcp) {
                 Ok(0) => return Ok(0),
                 Ok(_) => {},
                 Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
@@ -69,11 +84,12 @@ impl Write for TLStream {
                 Err(e) => return Err(e),
             };
         };
-        self.tls.writer().write(buf)
+        tls.writer().write(buf)
     }
     fn flush(&mut self) -> io::Result<()> {
-        while self.tls.wants_write() {
-            match self.tls.write_tls(&mut self.tcp) {
+        let Some(tls) = &mut self.tls else { return self.tcp.flush() };
+        while tls.wants_write() {
+            match tls.write_tls(&mut self.tcp) {
                 Ok(0) => return Err(ErrorKind::ConnectionAborted.into()),
                 Ok(_) => continue,
                 Err(e) if e.kind() == ErrorKind::WouldBlock => return Err(ErrorKind::WouldBlock.into()),
@@ -87,20 +103,21 @@ impl Write for TLStream {
 
 impl Read for TLStream {
     fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
-        while self.tls.wants_read() {
-            match self.tls.read_tls(&mut self.tcp) {
+        let Some(tls) = &mut self.tls else { return self.tcp.read(buffer) };
+        while tls.wants_read() {
+            match tls.read_tls(&mut self.tcp) {
                 Ok(0) => return Ok(0),
                 Ok(_) => {},
                 Err(e) if e.kind() == ErrorKind::WouldBlock => break,
                 Err(e) if e.kind() == ErrorKind::Interrupted => continue,
                 Err(e) => return Err(e),
             }
-            match self.tls.process_new_packets() {
+            match tls.process_new_packets() {
                 Ok(_) => {},
                 Err(_) => return Err(ErrorKind::ConnectionAborted.into()),
             }
         }
-        self.tls.reader().read(buffer)
+        tls.reader().read(buffer)
     }
 }
 
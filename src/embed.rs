@@ -0,0 +1,177 @@
+//! Compile a directory into the executable.
+//!
+//! In the application's `build.rs`:
+//! ```ignore
+//! fn main() {
+//!     simple_server::embed::generate("client", "client");
+//! }
+//! ```
+//! and when setting up the server:
+//! ```ignore
+//! server.http.mount("/", Embedded::new(simple_server::embedded_assets!("client")));
+//! // during development the live directory can be overlaid on top of the embedded copy
+//! if cfg!(debug_assertions) {
+//!     server.http.mount("/", Directory::new("client"));
+//! }
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write as _;
+use std::fs::{self, read_dir};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use base64::prelude::*;
+use camino::{Utf8Path, Utf8PathBuf};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use sha1::{Digest, Sha1};
+
+use crate::helpers::{is_compressible, mime_type};
+use crate::vfs::{list_children, V_stat, VfsBackend};
+use crate::{Body, V_listing};
+
+#[derive(Debug)]
+pub struct EmbeddedFile {
+    pub path: &'static str,
+    pub data: &'static [u8],
+    pub gzip: Option<&'static [u8]>,
+    pub mime: &'static str,
+    pub etag: &'static str,
+    /// seconds since the unix epoch
+    pub modified: u64,
+}
+
+/// expands to the `&'static [EmbeddedFile]` that `generate` wrote under `name`
+#[macro_export]
+macro_rules! embedded_assets {
+    ($name:literal) => {
+        include!(concat!(env!("OUT_DIR"), "/", $name, ".rs"))
+    };
+}
+
+/// meant to be called from a build script, writes `$OUT_DIR/{name}.rs` for `embedded_assets!`
+pub fn generate(dir: impl AsRef<Path>, name: &str) {
+    let dir = dir.as_ref();
+    let out_dir = PathBuf::from(std::env::var("OUT_DIR").expect("EMBED: OUT_DIR is only set for build scripts"));
+    let gzip_dir = out_dir.join(format!("{name}.gz"));
+    fs::create_dir_all(&gzip_dir).unwrap();
+
+    let root = fs::canonicalize(dir).unwrap_or_else(|e| panic!("EMBED: can't embed {}: {e}", dir.display()));
+    println!("cargo:rerun-if-changed={}", root.display());
+
+    let mut files = Vec::new();
+    collect_files(&root, Utf8Path::new(""), &mut files);
+    files.sort();
+
+    let mut code = String::from("&[\n");
+    for (index, path) in files.iter().enumerate() {
+        let sys_path = root.join(path);
+        println!("cargo:rerun-if-changed={}", sys_path.display());
+
+        let data = fs::read(&sys_path).unwrap();
+        let mime = mime_type(path);
+        let modified = fs::metadata(&sys_path)
+            .and_then(|md| md.modified())
+            .ok()
+            .and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map_or(0, |time| time.as_secs());
+
+        let mut sha1 = Sha1::new();
+        sha1.update(&data);
+        let etag = format!("\"{}\"", BASE64_STANDARD_NO_PAD.encode(sha1.finalize()));
+
+        // only keep the compressed variant if it actually saves something
+        let mut gzip = "None".to_owned();
+        if is_compressible(mime) {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+            encoder.write_all(&data).unwrap();
+            let compressed = encoder.finish().unwrap();
+            if compressed.len() < data.len() * 9 / 10 {
+                let gzip_path = gzip_dir.join(format!("{index}.gz"));
+                fs::write(&gzip_path, compressed).unwrap();
+                gzip = format!("Some(include_bytes!({:?}))", gzip_path.display().to_string());
+            }
+        }
+
+        writeln!(code, "    ::simple_server::embed::EmbeddedFile {{").unwrap();
+        writeln!(code, "        path: {:?},", path.as_str()).unwrap();
+        writeln!(code, "        data: include_bytes!({:?}),", sys_path.display().to_string()).unwrap();
+        writeln!(code, "        gzip: {gzip},").unwrap();
+        writeln!(code, "        mime: {mime:?},").unwrap();
+        writeln!(code, "        etag: {etag:?},").unwrap();
+        writeln!(code, "        modified: {modified},").unwrap();
+        writeln!(code, "    }},").unwrap();
+    }
+    code.push(']');
+    fs::write(out_dir.join(format!("{name}.rs")), code).unwrap();
+}
+
+fn collect_files(root: &Path, dir: &Utf8Path, files: &mut Vec<Utf8PathBuf>) {
+    for f in read_dir(root.join(dir)).unwrap().flatten() {
+        let f_ = f.file_name();
+        let Some(name) = f_.to_str() else { continue };
+
+        // we ignore dotfiles, same as the Directory backend
+        if name.starts_with('.') {
+            continue
+        }
+        let path = dir.join(name);
+        if f.file_type().unwrap().is_dir() {
+            collect_files(root, &path, files)
+        }
+        else {
+            files.push(path)
+        }
+    }
+}
+
+pub struct Embedded {
+    files: HashMap<Utf8PathBuf, &'static EmbeddedFile>,
+    dirs: HashSet<Utf8PathBuf>,
+}
+
+impl Embedded {
+    pub fn new(files: &'static [EmbeddedFile]) -> Self {
+        let mut embedded = Self {
+            files: HashMap::with_capacity(files.len()),
+            dirs: HashSet::new(),
+        };
+        for file in files {
+            let path = Utf8PathBuf::from(file.path);
+            for dir in path.ancestors().skip(1) {
+                embedded.dirs.insert(dir.into());
+            }
+            embedded.files.insert(path, file);
+        }
+        embedded
+    }
+}
+
+fn stat_of(file: &EmbeddedFile) -> V_stat {
+    V_stat {
+        mime: Some(file.mime),
+        etag: Some(file.etag),
+        gzip: file.gzip,
+        ..V_stat::file(file.data.len(), SystemTime::UNIX_EPOCH + Duration::from_secs(file.modified))
+    }
+}
+
+impl VfsBackend for Embedded {
+    fn get(&mut self, path: &Utf8Path) -> Option<Body> {
+        self.files.get(path).map(|file| Body::Static(file.data))
+    }
+
+    fn stat(&self, path: &Utf8Path) -> Option<V_stat> {
+        if self.dirs.contains(path) {
+            return Some(V_stat::dir())
+        }
+        self.files.get(path).map(|file| stat_of(file))
+    }
+
+    fn list(&self, dir: &Utf8Path) -> Vec<V_listing> {
+        let files = self.files.iter().map(|(path, file)| (path, stat_of(file)));
+        list_children(dir, files, &self.dirs)
+    }
+}
@@ -0,0 +1,204 @@
+//! Typed arguments for handlers.
+//!
+//! Every argument of a handler implements `FromRequest`, they're extracted left to right and
+//! the first one to fail answers the request in the handler's place:
+//! ```ignore
+//! server.http.add_service("/users/:id", Method::POST, |Params(user): Params<UserId>, Query(page): Query<Page>, Json(edit): Json<Edit>| {
+//!     ...
+//! });
+//! ```
+//! Anything that is `From<Request>` (like `Request` itself or `Utf8PathBuf`) still works and takes the whole request,
+//! so it should come last.
+
+use std::collections::HashMap;
+use std::mem;
+use std::net::SocketAddr;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::helpers::percent_encode;
+use crate::http::{Content, Method, Request, Response, Status};
+
+pub trait FromRequest: Sized {
+    fn from_request(request: &mut Request) -> Result<Self, Response>;
+}
+
+impl<T: From<Request>> FromRequest for T {
+    fn from_request(request: &mut Request) -> Result<Self, Response> {
+        Ok(mem::take(request).into())
+    }
+}
+
+macro_rules! impl_from_request_for_tuple {
+    ($($t:ident),+) => {
+        impl<$($t: FromRequest),+> FromRequest for ($($t,)+) {
+            fn from_request(request: &mut Request) -> Result<Self, Response> {
+                Ok(($($t::from_request(request)?,)+))
+            }
+        }
+    };
+}
+impl_from_request_for_tuple!(A, B);
+impl_from_request_for_tuple!(A, B, C);
+impl_from_request_for_tuple!(A, B, C, D);
+impl_from_request_for_tuple!(A, B, C, D, E);
+impl_from_request_for_tuple!(A, B, C, D, E, F);
+
+/// a plain text response explaining why the request couldn't be extracted
+pub fn rejection(status: Status, message: &str) -> Response {
+    let mut response: Response = status.into();
+    response.add_header("content-type", "text/plain; charset=utf-8");
+    response.body = Content::Bytes(message.as_bytes().to_vec());
+    response
+}
+
+/// the route's `:name` and `*name` captures, 400 if they don't fit `T`
+#[derive(Debug)]
+pub struct Params<T>(pub T);
+
+impl<T: DeserializeOwned> FromRequest for Params<T> {
+    fn from_request(request: &mut Request) -> Result<Self, Response> {
+        from_pairs(&request.params)
+            .map(Params)
+            .map_err(|e| rejection(Status::BadRequest, &format!("invalid path parameters: {e}")))
+    }
+}
+
+/// the query string, 400 if it doesn't fit `T`
+#[derive(Debug)]
+pub struct Query<T>(pub T);
+
+impl<T: DeserializeOwned> FromRequest for Query<T> {
+    fn from_request(request: &mut Request) -> Result<Self, Response> {
+        serde_urlencoded::from_str(&request.query)
+            .map(Query)
+            .map_err(|e| rejection(Status::BadRequest, &format!("invalid query: {e}")))
+    }
+}
+
+/// a `application/x-www-form-urlencoded` body, 415 for any other content type, 413 if it's over `FormLimits::max_total`
+/// and 422 if it doesn't fit `T`. For `multipart/form-data` see `form::Multipart`
+#[derive(Debug)]
+pub struct Form<T>(pub T);
+
+impl<T: DeserializeOwned> FromRequest for Form<T> {
+    fn from_request(request: &mut Request) -> Result<Self, Response> {
+        expect_content_type(request, "application/x-www-form-urlencoded")?;
+        if request.body.len() > request.form_limits.max_total {
+            return Err(rejection(Status::ContentTooLarge, "the form is over the size limit"))
+        }
+        serde_urlencoded::from_bytes(&request.body)
+            .map(Form)
+            .map_err(|e| rejection(Status::UnprocessableContent, &format!("invalid form: {e}")))
+    }
+}
+
+/// a `application/json` body, 415 for any other content type, 400 if it isn't json and 422 if it doesn't fit `T`.
+/// The error is sent back as json too, with the line and column the parser gave up at.
+///
+/// Returned from a handler it serializes `T` into the response body.
+#[derive(Debug)]
+pub struct Json<T>(pub T);
+
+impl<T: DeserializeOwned> FromRequest for Json<T> {
+    fn from_request(request: &mut Request) -> Result<Self, Response> {
+        let content_type = request.headers.get("content-type").map(String::as_str).unwrap_or("");
+        // application/problem+json and friends are json all the same
+        if !content_type.split(';').next().unwrap_or("").trim().ends_with("+json") {
+            expect_content_type(request, "application/json")?;
+        }
+        serde_json::from_slice(&request.body)
+            .map(Json)
+            .map_err(|e| {
+                let status = match e.classify() {
+                    serde_json::error::Category::Data => Status::UnprocessableContent,
+                    _ => Status::BadRequest,
+                };
+                let error = serde_json::json!({
+                    "error": format!("invalid json: {e}"),
+                    "line": e.line(),
+                    "column": e.column(),
+                });
+                let mut response: Response = Json(error).into();
+                response.status = status;
+                response
+            })
+    }
+}
+
+impl<T: Serialize> From<Json<T>> for Response {
+    fn from(Json(value): Json<T>) -> Response {
+        match serde_json::to_vec(&value) {
+            Ok(body) => {
+                let mut response: Response = Status::Ok.into();
+                response.add_header("content-type", "application/json");
+                response.body = Content::Bytes(body);
+                response
+            }
+            Err(e) => {
+                println!("HTTP_SERVER: couldn't serialize a json response: {e}");
+                rejection(Status::InternalServerError, "couldn't serialize the response")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Headers(pub HashMap<String, String>);
+
+impl FromRequest for Headers {
+    fn from_request(request: &mut Request) -> Result<Self, Response> {
+        Ok(Headers(request.headers.clone()))
+    }
+}
+
+/// the address of the client on the other end of the connection
+#[derive(Debug, Clone, Copy)]
+pub struct Peer(pub SocketAddr);
+
+impl FromRequest for Peer {
+    fn from_request(request: &mut Request) -> Result<Self, Response> {
+        request.peer
+            .map(Peer)
+            .ok_or_else(|| rejection(Status::InternalServerError, "the client address is unknown"))
+    }
+}
+
+impl FromRequest for Method {
+    fn from_request(request: &mut Request) -> Result<Self, Response> {
+        Ok(request.method)
+    }
+}
+
+/// the raw body
+impl FromRequest for Vec<u8> {
+    fn from_request(request: &mut Request) -> Result<Self, Response> {
+        Ok(mem::take(&mut request.body))
+    }
+}
+
+/// the body as text, 400 if it isn't utf-8
+impl FromRequest for String {
+    fn from_request(request: &mut Request) -> Result<Self, Response> {
+        String::from_utf8(mem::take(&mut request.body))
+            .map_err(|_| rejection(Status::BadRequest, "the body is not valid utf-8"))
+    }
+}
+
+fn expect_content_type(request: &Request, expected: &str) -> Result<(), Response> {
+    let content_type = request.headers.get("content-type").map(String::as_str).unwrap_or("");
+    let mime = content_type.split(';').next().unwrap_or("").trim();
+    match mime.eq_ignore_ascii_case(expected) {
+        true => Ok(()),
+        false => Err(rejection(Status::UnsupportedMediaType, &format!("expected a content type of {expected}"))),
+    }
+}
+
+/// serde_urlencoded is the one deserializer around that turns strings into numbers and such
+fn from_pairs<T: DeserializeOwned>(pairs: &HashMap<String, String>) -> Result<T, serde_urlencoded::de::Error> {
+    let encoded: Vec<String> = pairs.iter()
+        .map(|(key, value)| format!("{}={}", percent_encode(key), percent_encode(value)))
+        .collect();
+    serde_urlencoded::from_str(&encoded.join("&"))
+}
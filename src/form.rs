@@ -0,0 +1,488 @@
+//! `multipart/form-data` bodies, for the urlencoded kind see `extract::Form`.
+//!
+//! Parts bigger than `FormLimits::spool_threshold` are written out to `FormLimits::temp_dir`
+//! as they arrive and removed again when the `Part` is dropped, unless they're `persist`ed.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::extract::{rejection, FromRequest};
+use crate::http::{Request, Response, Status};
+
+#[derive(Debug, Clone)]
+pub struct FormLimits {
+    /// the largest value a field without a filename may have
+    pub max_field: usize,
+    /// the largest uploaded file
+    pub max_file: usize,
+    /// the largest body a form may have as a whole
+    pub max_total: usize,
+    pub spool_threshold: usize,
+    pub temp_dir: PathBuf,
+}
+
+impl Default for FormLimits {
+    fn default() -> Self {
+        Self {
+            max_field: 64 << 10,
+            max_file: 16 << 20,
+            max_total: 32 << 20,
+            spool_threshold: 1 << 20,
+            temp_dir: std::env::temp_dir(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum FormError {
+    NotMultipart,
+    MissingBoundary,
+    Malformed(&'static str),
+    TooLarge(String),
+    Io(io::Error),
+}
+
+impl std::fmt::Display for FormError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FormError::NotMultipart => write!(f, "expected a content type of multipart/form-data"),
+            FormError::MissingBoundary => write!(f, "the content type has no boundary"),
+            FormError::Malformed(why) => write!(f, "malformed multipart body: {why}"),
+            FormError::TooLarge(what) => write!(f, "{what} is over the size limit"),
+            FormError::Io(e) => write!(f, "couldn't spool an upload: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for FormError {}
+
+impl From<FormError> for Response {
+    fn from(e: FormError) -> Response {
+        let status = match e {
+            FormError::NotMultipart => Status::UnsupportedMediaType,
+            FormError::MissingBoundary | FormError::Malformed(_) => Status::BadRequest,
+            FormError::TooLarge(_) => Status::ContentTooLarge,
+            FormError::Io(_) => Status::InternalServerError,
+        };
+        rejection(status, &e.to_string())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Multipart {
+    pub parts: Vec<Part>,
+}
+
+#[derive(Debug)]
+pub struct Part {
+    pub name: String,
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+    /// lowercased keys
+    pub headers: HashMap<String, String>,
+    pub data: PartData,
+}
+
+#[derive(Debug)]
+pub enum PartData {
+    Memory(Vec<u8>),
+    Spooled(Spooled),
+}
+
+/// a part that was too big to keep in memory, the file goes away with it
+#[derive(Debug)]
+pub struct Spooled {
+    pub path: PathBuf,
+    pub size: usize,
+}
+
+impl Drop for Spooled {
+    fn drop(&mut self) {
+        if self.path != Path::new("") {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+impl Multipart {
+    pub fn parse(content_type: &str, body: &[u8], limits: &FormLimits) -> Result<Self, FormError> {
+        let mut reader = MultipartReader::new(content_type, Rc::new(limits.clone()))?;
+        reader.feed(body)?;
+        reader.finish()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Part> {
+        self.parts.iter().find(|part| part.name == name)
+    }
+    /// the value of a field that isn't a file
+    pub fn text(&self, name: &str) -> Option<&str> {
+        self.parts.iter()
+            .filter(|part| part.name == name && part.filename.is_none())
+            .find_map(Part::text)
+    }
+    pub fn files(&self) -> impl Iterator<Item = &Part> {
+        self.parts.iter().filter(|part| part.filename.is_some())
+    }
+}
+
+impl Part {
+    /// a part with its headers read and no data yet
+    fn from_head(head: &[u8]) -> Result<Self, FormError> {
+        use FormError::*;
+
+        let head = std::str::from_utf8(head).map_err(|_| Malformed("a part's headers aren't utf-8"))?;
+
+        let mut headers = HashMap::new();
+        for line in head.split("\r\n").filter(|line| !line.is_empty()) {
+            let (key, value) = line.split_once(':').ok_or(Malformed("a part header has no value"))?;
+            headers.insert(key.trim().to_ascii_lowercase(), value.trim().to_owned());
+        }
+
+        //REF: https://www.rfc-editor.org/rfc/rfc7578#section-4.2
+        let disposition = headers.get("content-disposition").ok_or(Malformed("a part has no content-disposition"))?;
+        let (kind, params) = parse_header_value(disposition);
+        if !kind.eq_ignore_ascii_case("form-data") { return Err(Malformed("a part isn't form-data")) }
+        let name = params.get("name").ok_or(Malformed("a part has no name"))?.clone();
+        let filename = params.get("filename").cloned();
+
+        Ok(Part {
+            name,
+            filename,
+            content_type: headers.get("content-type").cloned(),
+            headers,
+            data: PartData::Memory(Vec::new()),
+        })
+    }
+
+    pub fn size(&self) -> usize {
+        match &self.data {
+            PartData::Memory(data) => data.len(),
+            PartData::Spooled(file) => file.size,
+        }
+    }
+    /// None if the part was spooled or isn't utf-8
+    pub fn text(&self) -> Option<&str> {
+        match &self.data {
+            PartData::Memory(data) => std::str::from_utf8(data).ok(),
+            PartData::Spooled(_) => None,
+        }
+    }
+    pub fn bytes(&self) -> io::Result<Vec<u8>> {
+        match &self.data {
+            PartData::Memory(data) => Ok(data.clone()),
+            PartData::Spooled(file) => fs::read(&file.path),
+        }
+    }
+    /// moves the data to `path`, keeping it around after the part is dropped
+    pub fn persist(mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        match &mut self.data {
+            PartData::Memory(data) => fs::write(path, data),
+            PartData::Spooled(file) => {
+                // rename doesn't work across file systems
+                if fs::rename(&file.path, path.as_ref()).is_err() {
+                    fs::copy(&file.path, path.as_ref())?;
+                    fs::remove_file(&file.path)?;
+                }
+                file.path = PathBuf::new();
+                Ok(())
+            }
+        }
+    }
+}
+
+impl FromRequest for Multipart {
+    fn from_request(request: &mut Request) -> Result<Self, Response> {
+        // the server parses multipart bodies as they come in
+        if let Some(parsed) = request.multipart.take() {
+            return parsed.map_err(Response::from)
+        }
+        let content_type = request.headers.get("content-type").map(String::as_str).unwrap_or("");
+        Multipart::parse(content_type, &request.body, &request.form_limits).map_err(Response::from)
+    }
+}
+
+/// the most a part's headers may take up
+const MAX_PART_HEAD: usize = 16 << 10;
+
+/// parses a multipart body fed to it piece by piece, so big uploads go to disk
+/// as they arrive rather than being buffered whole first
+#[derive(Debug)]
+pub struct MultipartReader {
+    limits: Rc<FormLimits>,
+    delimiter: Vec<u8>,
+    /// what couldn't be told apart from the start of a delimiter yet
+    pending: Vec<u8>,
+    stage: Stage,
+    part: Option<Part>,
+    /// open while the current part is being spooled
+    file: Option<fs::File>,
+    multipart: Multipart,
+    total: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Stage {
+    Preamble,
+    /// right after a delimiter, either a line break or the closing `--` follows
+    Delimited,
+    Head,
+    Body,
+    Epilogue,
+}
+
+impl MultipartReader {
+    pub fn new(content_type: &str, limits: Rc<FormLimits>) -> Result<Self, FormError> {
+        let (mime, params) = parse_header_value(content_type);
+        if !mime.eq_ignore_ascii_case("multipart/form-data") { return Err(FormError::NotMultipart) }
+        let boundary = params.get("boundary").filter(|b| !b.is_empty()).ok_or(FormError::MissingBoundary)?;
+
+        //REF: https://www.rfc-editor.org/rfc/rfc2046#section-5.1.1
+        Ok(Self {
+            limits,
+            delimiter: format!("\r\n--{boundary}").into_bytes(),
+            // the first delimiter may come right at the start, without the crlf before it
+            pending: b"\r\n".to_vec(),
+            stage: Stage::Preamble,
+            part: None,
+            file: None,
+            multipart: Multipart::default(),
+            total: 0,
+        })
+    }
+
+    pub fn feed(&mut self, data: &[u8]) -> Result<(), FormError> {
+        use FormError::*;
+
+        self.total += data.len();
+        if self.total > self.limits.max_total { return Err(TooLarge("the form".into())) }
+        self.pending.extend_from_slice(data);
+
+        loop {
+            // how much of `pending` can't be the start of a delimiter
+            let settled = self.pending.len().saturating_sub(self.delimiter.len() - 1);
+            match self.stage {
+                Stage::Preamble => match find(&self.pending, &self.delimiter) {
+                    Some(at) => {
+                        self.pending.drain(..at + self.delimiter.len());
+                        self.stage = Stage::Delimited;
+                    }
+                    None => {
+                        self.pending.drain(..settled);
+                        return Ok(())
+                    }
+                },
+                Stage::Delimited => {
+                    if self.pending.len() < 2 { return Ok(()) }
+                    match &self.pending[..2] {
+                        b"--" => self.stage = Stage::Epilogue,
+                        b"\r\n" => {
+                            self.pending.drain(..2);
+                            self.stage = Stage::Head;
+                        }
+                        _ => return Err(Malformed("a boundary isn't followed by a line break")),
+                    }
+                }
+                Stage::Head => {
+                    let end = match self.pending.starts_with(b"\r\n") {
+                        true => Some((0, 2)),
+                        false => find(&self.pending, b"\r\n\r\n").map(|at| (at, at + 4)),
+                    };
+                    let Some((head, body)) = end else {
+                        if self.pending.len() > MAX_PART_HEAD { return Err(TooLarge("a part's headers".into())) }
+                        return Ok(())
+                    };
+                    self.part = Some(Part::from_head(&self.pending[..head])?);
+                    self.pending.drain(..body);
+                    self.stage = Stage::Body;
+                }
+                Stage::Body => {
+                    let part = self.part.as_mut().unwrap();
+                    match find(&self.pending, &self.delimiter) {
+                        Some(at) => {
+                            append(part, &mut self.file, &self.pending[..at], &self.limits)?;
+                            self.pending.drain(..at + self.delimiter.len());
+                            self.file = None;
+                            self.multipart.parts.push(self.part.take().unwrap());
+                            self.stage = Stage::Delimited;
+                        }
+                        None => {
+                            append(part, &mut self.file, &self.pending[..settled], &self.limits)?;
+                            self.pending.drain(..settled);
+                            return Ok(())
+                        }
+                    }
+                }
+                Stage::Epilogue => {
+                    self.pending.clear();
+                    return Ok(())
+                }
+            }
+        }
+    }
+
+    /// the parts, once the body is all in
+    pub fn finish(self) -> Result<Multipart, FormError> {
+        match self.stage {
+            Stage::Epilogue => Ok(self.multipart),
+            Stage::Preamble => Err(FormError::Malformed("no opening boundary")),
+            _ => Err(FormError::Malformed("no closing boundary")),
+        }
+    }
+}
+
+/// adds to a part's data, moving it to a file once it outgrows `FormLimits::spool_threshold`
+fn append(part: &mut Part, file: &mut Option<fs::File>, data: &[u8], limits: &FormLimits) -> Result<(), FormError> {
+    let limit = match part.filename {
+        Some(_) => limits.max_file,
+        None => limits.max_field,
+    };
+    if part.size() + data.len() > limit { return Err(FormError::TooLarge(format!("the field '{}'", part.name))) }
+
+    match &mut part.data {
+        PartData::Memory(memory) if memory.len() + data.len() > limits.spool_threshold => {
+            let (mut spool, path) = create_temp_file(&limits.temp_dir).map_err(FormError::Io)?;
+            let memory = mem::take(memory);
+            // the part owns the file before anything is written, so it's removed if writing fails
+            part.data = PartData::Spooled(Spooled { path, size: memory.len() + data.len() });
+            spool.write_all(&memory).map_err(FormError::Io)?;
+            spool.write_all(data).map_err(FormError::Io)?;
+            *file = Some(spool);
+        }
+        PartData::Memory(memory) => memory.extend_from_slice(data),
+        PartData::Spooled(spooled) => {
+            file.as_mut().unwrap().write_all(data).map_err(FormError::Io)?;
+            spooled.size += data.len();
+        }
+    }
+    Ok(())
+}
+
+/// a new file only we can read, so nobody can plant a symlink or read the upload in a shared temp dir
+fn create_temp_file(dir: &Path) -> io::Result<(fs::File, PathBuf)> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    loop {
+        let path = dir.join(format!("simple-server-upload-{:016x}", fastrand::u64(..)));
+        match options.open(&path) {
+            Ok(file) => return Ok((file, path)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// splits `form-data; name="a"; filename="b"` into the value and its parameters, keys lowercased
+fn parse_header_value(value: &str) -> (&str, HashMap<String, String>) {
+    let (value, mut rest) = value.split_once(';').unwrap_or((value, ""));
+    let mut params = HashMap::new();
+    loop {
+        rest = rest.trim_start_matches([' ', '\t', ';']);
+        let Some((key, after)) = rest.split_once('=') else { break };
+        let key = key.trim().to_ascii_lowercase();
+        let after = after.trim_start();
+
+        //REF: https://www.rfc-editor.org/rfc/rfc9110#section-5.6.4
+        if let Some(quoted) = after.strip_prefix('"') {
+            let mut param = String::new();
+            let mut chars = quoted.char_indices();
+            let mut end = quoted.len();
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '\\' => if let Some((_, escaped)) = chars.next() { param.push(escaped) },
+                    '"' => { end = i + 1; break }
+                    c => param.push(c),
+                }
+            }
+            params.insert(key, param);
+            rest = &quoted[end..];
+        }
+        else {
+            let (param, after) = after.split_once(';').unwrap_or((after, ""));
+            params.insert(key, param.trim().to_owned());
+            rest = after;
+        }
+    }
+    (value.trim(), params)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTENT_TYPE: &str = "multipart/form-data; boundary=XyZ";
+
+    /// a file whose data looks like the start of a delimiter more than once
+    fn body() -> Vec<u8> {
+        let mut body = b"a preamble to ignore\r\n--XyZ\r\n".to_vec();
+        body.extend_from_slice(b"Content-Disposition: form-data; name=\"title\"\r\n\r\nhello\r\n--XyZ\r\n");
+        body.extend_from_slice(b"Content-Disposition: form-data; name=\"upload\"; filename=\"a.txt\"\r\n");
+        body.extend_from_slice(b"Content-Type: text/plain\r\n\r\n");
+        body.extend_from_slice(b"line one\r\n--Xy line two\r\n-- \r\n\r\n--X\r\n--XyZ\r\n");
+        body.extend_from_slice(b"Content-Disposition: form-data; name=\"empty\"\r\n\r\n\r\n--XyZ--\r\nan epilogue");
+        body
+    }
+    fn defaults() -> Rc<FormLimits> {
+        Rc::new(FormLimits::default())
+    }
+    fn feed_bytewise(body: &[u8], limits: Rc<FormLimits>) -> Result<Multipart, FormError> {
+        let mut reader = MultipartReader::new(CONTENT_TYPE, limits)?;
+        for byte in body {
+            reader.feed(&[*byte])?;
+        }
+        reader.finish()
+    }
+
+    #[test]
+    fn bytewise_matches_whole() {
+        let whole = Multipart::parse(CONTENT_TYPE, &body(), &FormLimits::default()).unwrap();
+        let bytewise = feed_bytewise(&body(), defaults()).unwrap();
+        for form in [whole, bytewise] {
+            assert_eq!(form.parts.len(), 3);
+            assert_eq!(form.text("title"), Some("hello"));
+            assert_eq!(form.text("empty"), Some(""));
+            let upload = form.get("upload").unwrap();
+            assert_eq!(upload.filename.as_deref(), Some("a.txt"));
+            assert_eq!(upload.content_type.as_deref(), Some("text/plain"));
+            assert_eq!(upload.text(), Some("line one\r\n--Xy line two\r\n-- \r\n\r\n--X"));
+        }
+    }
+
+    #[test]
+    fn spools_as_it_grows() {
+        let limits = Rc::new(FormLimits { spool_threshold: 8, ..FormLimits::default() });
+        let form = feed_bytewise(&body(), limits).unwrap();
+        let upload = form.get("upload").unwrap();
+        let PartData::Spooled(spooled) = &upload.data else { panic!("the upload wasn't spooled") };
+        let path = spooled.path.clone();
+        assert_eq!(upload.bytes().unwrap(), b"line one\r\n--Xy line two\r\n-- \r\n\r\n--X");
+        assert!(matches!(form.get("title").unwrap().data, PartData::Memory(_)));
+
+        drop(form);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn refuses_what_doesnt_fit() {
+        let limits = Rc::new(FormLimits { max_file: 10, ..FormLimits::default() });
+        assert!(matches!(feed_bytewise(&body(), limits), Err(FormError::TooLarge(what)) if what.contains("upload")));
+
+        let limits = Rc::new(FormLimits { max_total: 100, ..FormLimits::default() });
+        assert!(matches!(feed_bytewise(&body(), limits), Err(FormError::TooLarge(_))));
+
+        let mut cut = body();
+        cut.truncate(cut.len() - 20);
+        assert!(matches!(feed_bytewise(&cut, defaults()), Err(FormError::Malformed("no closing boundary"))));
+        assert!(matches!(feed_bytewise(b"no boundary anywhere", defaults()), Err(FormError::Malformed("no opening boundary"))));
+        assert!(matches!(MultipartReader::new("multipart/form-data", defaults()), Err(FormError::MissingBoundary)));
+    }
+}
@@ -9,8 +9,9 @@ use chrono::*;
 
 use crate::*;
 use crate::http::*;
+use crate::smithy::remove_dot_segments;
 
-pub fn get_domain_certs(path: &str) -> Vec<CertificateDer> {
+pub fn get_domain_certs(path: &str) -> Vec<CertificateDer<'_>> {
     CertificateDer::pem_file_iter(path)
         .unwrap()
         .map(|cert| cert.unwrap())
@@ -48,7 +49,8 @@ impl From<Status> for Response {
             version: Version::V_1_1,
             status,
             headers: vec![],
-            body: "".into(),
+            body: Content::Empty,
+            upgrade: None,
         }
     }
 }
@@ -60,7 +62,8 @@ impl From<Utf8PathBuf> for Response {
             version: Version::V_1_1,
             status: Status::Ok,
             headers: vec![],
-            body: path,
+            body: Content::Path(path),
+            upgrade: None,
         }
     }
 }
@@ -123,17 +126,162 @@ impl From<()> for Response {
             version: Version::V_1_1,
             status: Status::NotFound,
             headers: vec![],
-            body: Utf8PathBuf::new(),
+            body: Content::Empty,
+            upgrade: None,
         }
     }
 }
 
+/// lets handlers bail out with `Err(Status::Forbidden)` and the like
+impl<T: Into<Response>, E: Into<Response>> From<Result<T, E>> for Response {
+    fn from(result: Result<T, E>) -> Response {
+        match result {
+            Ok(ok) => ok.into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl From<Redirect> for Response {
+    fn from(redirect: Redirect) -> Response {
+        let mut response: Response = redirect.status.into();
+        response.add_header("location", &redirect.location);
+        response
+    }
+}
+
 impl From<Request> for Utf8PathBuf {
     fn from(r: Request) -> Utf8PathBuf {
         r.path
     }
 }
 
+pub fn mime_type(path: &Utf8Path) -> &'static str {
+    match path.extension().map(|ext| ext.to_ascii_lowercase()).as_deref() {
+        Some("html" | "htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js" | "mjs") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("xml") => "application/xml",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("ico") => "image/x-icon",
+        Some("mp3") => "audio/mpeg",
+        Some("wav") => "audio/wav",
+        Some("ogg") => "audio/ogg",
+        Some("mp4") => "video/mp4",
+        Some("webm") => "video/webm",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        Some("ttf") => "font/ttf",
+        Some("wasm") => "application/wasm",
+        Some("pdf") => "application/pdf",
+        Some("zip") => "application/zip",
+        Some("gz") => "application/gzip",
+        Some("tar") => "application/x-tar",
+        _ => "application/octet-stream",
+    }
+}
+
+/// whether gzipping a file of this type is likely to be worth it
+pub fn is_compressible(mime: &str) -> bool {
+    mime.starts_with("text/")
+        || mime.starts_with("application/json")
+        || mime.starts_with("application/xml")
+        || mime.starts_with("application/wasm")
+        || mime.starts_with("image/svg+xml")
+}
+
+//REF: https://www.rfc-editor.org/rfc/rfc3986#section-5.2
+/// makes a `Location` absolute, `base` being the path the request was for
+pub fn resolve_location(location: &str, scheme: &str, host: &str, base: &str) -> String {
+    if location.split_once(':').is_some_and(|(scheme, _)| !scheme.is_empty() && !scheme.contains(['/', '?', '#'])) {
+        return location.to_owned()
+    }
+    if let Some(authority) = location.strip_prefix("//") {
+        return format!("{scheme}://{authority}")
+    }
+    let split = location.find(['?', '#']).unwrap_or(location.len());
+    let (path, suffix) = location.split_at(split);
+    let merged = match path {
+        "" => base.to_owned(),
+        path if path.starts_with('/') => path.to_owned(),
+        path => format!("{}{path}", &base[..base.rfind('/').map_or(0, |i| i + 1)]),
+    };
+    let segments: Vec<&str> = merged.split('/').skip(1).collect();
+    format!("{scheme}://{host}{}{suffix}", remove_dot_segments(&segments))
+}
+
+/// percent-encodes everything but the RFC 3986 unreserved characters, for building links to vfs paths
+pub fn percent_encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for byte in s.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+/// percent-encodes each segment of a decoded path, leaving the slashes
+pub fn encode_path(path: &str) -> String {
+    let segments: Vec<String> = path.split('/').map(percent_encode).collect();
+    segments.join("/")
+}
+
+/// None on a malformed escape, the caller decides which decoded bytes are acceptable
+pub fn percent_decode(s: &[u8]) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(s.len());
+    let mut bytes = s.iter();
+    while let Some(&byte) = bytes.next() {
+        match byte {
+            b'%' => {
+                let hi = (*bytes.next()? as char).to_digit(16)?;
+                let lo = (*bytes.next()? as char).to_digit(16)?;
+                decoded.push((hi * 16 + lo) as u8);
+            }
+            byte => decoded.push(byte),
+        }
+    }
+    Some(decoded)
+}
+
+pub fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub fn escape_json(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
 pub fn throw_reader_at_writer(rd: &mut impl Read, wr: &mut impl Write) -> io::Result<()> {
     io::copy(rd, wr)?;
     wr.flush()?;
@@ -209,4 +357,7 @@ pub trait SendTo {
 pub trait Parser<T, E> {
     /// T is optional so that handshakes can be made to consume bytes transparently
     fn parse<'b>(&mut self, buf: &'b [u8]) -> Result<(Option<T>, &'b [u8]), E>;
+    /// the most unparsed bytes it could need at once before it either parses or refuses them,
+    /// reads are capped at this
+    fn room(&self) -> usize { usize::MAX }
 }
\ No newline at end of file
@@ -1,84 +1,137 @@
 use core::str;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
 use std::process::exit;
 use std::{collections::HashMap, marker::PhantomData};
-use std::path::PathBuf;
+use std::net::SocketAddr;
+use std::cell::RefCell;
+use std::rc::Rc;
 use std::time::SystemTime;
-use std::sync::mpsc;
 
 use chrono::{DateTime, Utc};
 
-use camino::Utf8PathBuf;
+use camino::{Utf8Path, Utf8PathBuf};
 
-use crate::helpers::path_is_sane;
+use crate::extract::FromRequest;
+use crate::form::{FormError, FormLimits, Multipart};
+use crate::helpers::{encode_path, mime_type, path_is_sane, resolve_location};
+use crate::middleware::{Middleware, Next};
+use crate::router::{panic_message, Route, Router, Service};
 use crate::smithy::HttpSmithText;
-use crate::TLS::TLStream;
-use crate::Vfs;
+use crate::state::AppState;
+use crate::vfs::VfsBackend;
+use crate::server_G::Adopter;
+use crate::websocket::{self, Upgrade, WsHandshakeConfig, WsHandshaker};
+use crate::{AutoIndex, Mount, Vfs};
 
 
-pub struct Service {
-    path: PathBuf,
-    method: Method,
-    handler: Box<dyn Handle>,
-}
-
-impl Service {
-    pub fn new<I, O>(path_str: &str, method: Method, function: impl FnMut(I) -> O + 'static) -> Self 
-    where
-        I: From<Request> + 'static,
-        O: Into<Response> + 'static,
-    {
-        Service {
-            path: path_str.parse().unwrap(),
-            method,
-            handler: Box::new(Handler::new(function)),
-        }
-    }
-}
-
 pub struct HttpServer {
-    pub services: Vec<Service>,
+    pub router: Router,
+    pub layers: Vec<Box<dyn Middleware>>,
     pub homepage: Utf8PathBuf,
-    pub not_found: Utf8PathBuf,
+    pub error_pages: HashMap<Status, ErrorPage>,
+    /// exact request paths that have moved, checked before routing
+    pub redirects: HashMap<Utf8PathBuf, Redirect>,
     pub file_system: Vfs,
     pub smith: HttpSmithText,
-    pub websocket: Option<mpsc::Sender<TLStream>>,
+    pub form_limits: Rc<FormLimits>,
+    /// the largest body a request that isn't a form may have, it's refused from the head alone
+    pub max_body: usize,
+    pub state: Rc<AppState>,
+    requests: u64,
 }
 
 impl HttpServer {
     pub fn new() -> Self {
         Self {
-            services: Vec::with_capacity(4),
+            router: Router::new(),
+            layers: Vec::new(),
             homepage: Utf8PathBuf::new(),
-            not_found: Utf8PathBuf::new(),
-            file_system: Vfs {
-                files: HashMap::new(),
-                client_dir: Utf8PathBuf::new()
-            },
+            error_pages: HashMap::new(),
+            redirects: HashMap::new(),
+            file_system: Vfs::new(),
             smith: HttpSmithText{},
-            websocket: None,
+            form_limits: Rc::default(),
+            max_body: 8 << 20,
+            state: Rc::default(),
+            requests: 0,
         }
     }
-    pub fn add_service<I, O>(&mut self, path: &str, method: Method, function: impl FnMut(I) -> O + 'static)
+    /// `function` takes any number of `FromRequest` arguments, see `extract`
+    pub fn add_service<Args, O>(&mut self, path: &str, method: Method, function: impl Endpoint<Args, O> + 'static) -> &mut Service
     where
-        I: From<Request> + 'static,
-        O: Into<Response> + 'static,
+        Args: 'static,
+        O: 'static,
     {
-        let service = Service::new(path, method, function);
-        self.services.push(service);
+        self.router.add(path, method, Box::new(Handler::new(function)))
+    }
+    /// answers websocket handshakes on `path`, the upgraded connections are handed to the `WsServer` behind `adopter`
+    pub fn add_websocket(&mut self, path: &str, adopter: Adopter<WsHandshaker>, config: WsHandshakeConfig) -> &mut Service {
+        self.add_service(path, Method::GET, move |request: Request| -> Response {
+            match websocket::upgrade(&request, &config) {
+                Ok((mut response, agreement)) => {
+                    response.upgrade = Some(Box::new(Upgrade { agreement, adopter: adopter.clone() }));
+                    response
+                },
+                Err(response) => response,
+            }
+        })
+    }
+    /// runs `middleware` around every request in the order they're added, see `middleware`
+    pub fn add_layer(&mut self, middleware: impl Middleware + 'static) {
+        self.layers.push(Box::new(middleware));
     }
     pub fn set_homepage(&mut self, path: &str) {
         self.homepage = path.into();
     }
     pub fn set_404_page(&mut self, path: &str) {
-        self.not_found = path.into();
+        self.set_error_page(Status::NotFound, path);
+    }
+    /// the page sent along with error responses that don't have a body of their own
+    pub fn set_error_page(&mut self, status: Status, path: &str) {
+        self.error_pages.insert(status, ErrorPage::Page(path.into()));
+    }
+    /// `from` is a path like `/old/page.html`, the redirect applies to every method
+    pub fn add_redirect(&mut self, from: &str, redirect: Redirect) {
+        self.redirects.insert(from.into(), redirect);
+    }
+    pub fn set_error_generator(&mut self, status: Status, generator: impl FnMut(Status) -> Response + 'static) {
+        self.error_pages.insert(status, ErrorPage::Generator(Box::new(generator)));
     }
     pub fn set_client_directory(&mut self, path: &str) {
         self.file_system.client_dir = path.into();
     }
+    /// makes `value` available to handlers through the `State<T>` extractor, see `state`.
+    /// the returned handle is for using it outside of handlers
+    pub fn add_state<T: 'static>(&mut self, value: T) -> Rc<RefCell<T>> {
+        Rc::make_mut(&mut self.state).insert(value)
+    }
+    pub fn add_shared_state<T: 'static>(&mut self, shared: Rc<RefCell<T>>) {
+        Rc::make_mut(&mut self.state).insert_shared(shared)
+    }
+    pub fn set_form_limits(&mut self, limits: FormLimits) {
+        self.form_limits = limits.into();
+    }
+    /// forms go by `FormLimits::max_total` instead
+    pub fn set_max_body(&mut self, bytes: usize) {
+        self.max_body = bytes;
+    }
+    pub fn set_cache_budget(&mut self, bytes: usize) {
+        self.file_system.budget = bytes;
+    }
+    pub fn set_stream_threshold(&mut self, bytes: usize) {
+        self.file_system.stream_threshold = bytes;
+    }
+    pub fn set_autoindex(&mut self, format: Option<AutoIndex>) {
+        self.file_system.autoindex = format;
+    }
+    pub fn mount(&mut self, prefix: &str, backend: impl VfsBackend + 'static) -> &mut Mount {
+        self.file_system.mount(prefix, backend)
+    }
     pub(crate) fn init(&mut self) {
-        if self.file_system.client_dir == Utf8PathBuf::new() {
+        if self.file_system.client_dir == Utf8PathBuf::new() && self.file_system.mounts.is_empty() {
             println!("ERROR: no client directory set");
-            println!("USAGE: call 'set_client_directory()' b4 'serve()");
+            println!("USAGE: call 'set_client_directory()' or 'mount()' b4 'serve()");
             exit(1);
         }
         if self.homepage == Utf8PathBuf::new() {
@@ -86,24 +139,23 @@ impl HttpServer {
             println!("USAGE: call 'set_homepage()' b4 'serve()");
             exit(1);
         }
-        if self.not_found == Utf8PathBuf::new() {
-            println!("ERROR: no 404 page set");
-            println!("USAGE: call 'set_404_page()' b4 'serve()");
-            exit(1);
-        }
 
-        self.file_system.build_cache();
+        self.file_system.init();
 
-        if self.file_system.get(&self.homepage).is_none() {
+        if !self.file_system.contains(&self.homepage) {
             println!("SERVER: the homepage [{}] is not in the client directory", self.homepage);
             println!("SUGGESTION: maybe u forgot to use a path relative to the client dir?");
             exit(1)
         };
-        if self.file_system.get(&self.not_found).is_none() {
-            println!("SERVER: the 404 page [{}] is not in the client directory", self.not_found);
-            println!("SUGGESTION: maybe u forgot to use a path relative to the client dir?");
-            exit(1)
-        };
+        for (status, page) in &self.error_pages {
+            if let ErrorPage::Page(path) = page {
+                if !self.file_system.contains(path) {
+                    println!("SERVER: the {} page [{path}] is not in the client directory", status.code());
+                    println!("SUGGESTION: maybe u forgot to use a path relative to the client dir?");
+                    exit(1)
+                }
+            }
+        }
     }
 }
 
@@ -115,43 +167,186 @@ impl Default for HttpServer {
 
 impl HttpServer {
     pub fn handle_request(&mut self, mut request: Request) -> Response {
-        let mut response = ().into();
-        for service in &mut self.services {
-            //println!("service path: {:?}, request path: {:?}", service.path, request.path);
-            if request.path.starts_with(&service.path) && (request.method == service.method || service.method == Method::ANY) {
-                request.path = request.path.strip_prefix(&service.path).unwrap().to_path_buf();
-                response = service.handler.handle(request);
-                break
+        request.form_limits = self.form_limits.clone();
+        request.state = self.state.clone();
+        request.id = self.requests;
+        self.requests += 1;
+
+        let scheme = match request.secure { true => "https", false => "http" };
+        let host = request.headers.get("host").cloned().unwrap_or_default();
+        // locations are resolved against the target as it was sent, not the decoded path
+        let target = encode_path(request.path.as_str());
+
+        // taken out for the duration so the innermost layer can borrow the rest of self
+        let mut layers = mem::take(&mut self.layers);
+        let id = request.id;
+        // services catch their own panics, this is for the global layers
+        let response = panic::catch_unwind(AssertUnwindSafe(|| {
+            Next::new(&mut layers, &mut |request| self.dispatch(request)).run(request)
+        }));
+        self.layers = layers;
+
+        let mut response = match response {
+            Ok(response) => response,
+            Err(payload) => {
+                println!("HTTP_SERVER: request {id} panicked in a middleware layer: {}", panic_message(&*payload));
+                let mut response: Response = Status::InternalServerError.into();
+                self.error_page(&mut response, false);
+                response
             }
-        }
-        let mut dropped = false;
-        if response.body == PathBuf::new() {
-            response.body = self.homepage.clone();
-        }
-        if !path_is_sane(&response.body) { dropped = true }
-        let mut body_size = 0;
-        match self.file_system.get(&response.body) {
-            Some(file) => body_size = file.data.len(),
-            None => dropped = true,
         };
-        if dropped {
-            response.status = Status::NotFound;
-            response.body = self.not_found.clone();
-            body_size = self.file_system.get_size(&self.not_found).unwrap_or(0)
+        // without a host (HTTP/1.0) there's nothing to make it absolute against, relative locations are allowed anyhow
+        let location = response.headers.iter_mut().find(|Header(key, _)| *key == "location");
+        if let (Some(Header(_, location)), false) = (location, host.is_empty()) {
+            *location = resolve_location(location, scheme, &host, &target);
         }
+
+        self.finish(response)
+    }
+    /// an error response with its error page, for when there's no request to handle
+    pub fn error_response(&mut self, status: Status) -> Response {
+        let mut response: Response = status.into();
+        self.error_page(&mut response, false);
+        self.finish(response)
+    }
+    /// the built-in error page, for when even the configured one can't be sent
+    pub fn fallback_response(&mut self, status: Status) -> Response {
+        self.finish(fallback_page(status))
+    }
+    fn finish(&mut self, mut response: Response) -> Response {
+        let body_size = match &response.body {
+            Content::Path(path) => self.file_system.get_size(path).unwrap_or(0),
+            Content::Bytes(bytes) => bytes.len(),
+            Content::Static(data) => data.len(),
+            Content::Empty => 0,
+        };
         //format: Sun, 06 Nov 1994 08:49:37 GMT
         let time: DateTime<Utc> = SystemTime::now().into();
         let timestamp = time.to_rfc2822();
         response.add_header("server", "simple-server");
         response.add_header("date", &timestamp);
-        response.add_header("content-length", &format!("{}", body_size));
+        // a 1xx has no body to speak of
+        if response.status.code() >= 200 {
+            response.add_header("content-length", &format!("{}", body_size));
+        }
+        response
+    }
+    fn dispatch(&mut self, mut request: Request) -> Response {
+        if let Some(redirect) = self.redirects.get(&request.path) {
+            return redirect.clone().into()
+        }
+        let target = request.path.clone();
+        let query = request.query.clone();
+        let if_none_match = request.headers.get("if-none-match").cloned();
+        let accepts_gzip = request.headers.get("accept-encoding").is_some_and(|enc| enc.contains("gzip"));
+        let mut response = match self.router.find(request.path.as_str(), &request.method) {
+            Route::Found { endpoint, params, rest } => {
+                request.path = rest.into();
+                request.params = params;
+                self.router.service(endpoint).handle(request)
+            }
+            Route::MethodNotAllowed(methods) => {
+                let mut response: Response = Status::MethodNotAllowed.into();
+                let allow: Vec<&str> = methods.iter().map(Method::to_str).collect();
+                response.add_header("allow", &allow.join(", "));
+                response.body = Content::Empty;
+                response
+            }
+            Route::NotFound => ().into(),
+        };
+        if let Content::Path(path) = &response.body {
+            let path = match path.as_str() {
+                "" => self.homepage.clone(),
+                _ => path.clone(),
+            };
+            self.resolve(&mut response, path, &target, &query);
+            self.negotiate(&mut response, if_none_match.as_deref(), accepts_gzip);
+        }
+        if response.status.is_error() && matches!(response.body, Content::Empty) {
+            self.error_page(&mut response, accepts_gzip);
+        }
         response
     }
+    fn error_page(&mut self, response: &mut Response, accepts_gzip: bool) {
+        match self.error_pages.get_mut(&response.status) {
+            Some(ErrorPage::Page(path)) if self.file_system.contains(path) => {
+                response.body = Content::Path(path.clone());
+                self.negotiate(response, None, accepts_gzip);
+            }
+            Some(ErrorPage::Generator(generate)) => {
+                let page = generate(response.status);
+                response.headers.extend(page.headers);
+                response.body = page.body;
+            }
+            _ => {
+                let page = fallback_page(response.status);
+                response.headers.extend(page.headers);
+                response.body = page.body;
+            }
+        }
+    }
+    fn resolve(&mut self, response: &mut Response, mut path: Utf8PathBuf, target: &Utf8Path, query: &str) {
+        if path_is_sane(&path) && self.file_system.is_dir(&path) {
+            // without the trailing slash relative links inside the directory would resolve against its parent
+            if !target.as_str().ends_with('/') {
+                let mut location = format!("{}/", encode_path(target.as_str()));
+                if !query.is_empty() {
+                    location = format!("{location}?{query}");
+                }
+                response.status = Status::MovedPermanently;
+                response.add_header("location", &location);
+                response.body = Content::Empty;
+                return
+            }
+            match (self.file_system.index_of(&path), self.file_system.autoindex_for(&path)) {
+                (Some(index), _) => path = index,
+                (None, Some(format)) => {
+                    let (listing, content_type) = self.file_system.render_listing(&path, target, format);
+                    response.add_header("content-type", content_type);
+                    response.body = Content::Bytes(listing);
+                    return
+                }
+                (None, None) => {},
+            }
+        }
+        if !path_is_sane(&path) || !self.file_system.contains(&path) {
+            response.status = Status::NotFound;
+            response.body = Content::Empty;
+            return
+        }
+        response.body = Content::Path(path);
+    }
+    /// fills in what the vfs knows about the file: content type, etag and the precompressed variant
+    fn negotiate(&mut self, response: &mut Response, if_none_match: Option<&str>, accepts_gzip: bool) {
+        let Content::Path(path) = &response.body else { return };
+        let Some(stat) = self.file_system.stat(path) else { return };
+
+        if !response.headers.iter().any(|Header(key, _)| *key == "content-type") {
+            response.add_header("content-type", stat.mime.unwrap_or_else(|| mime_type(path)));
+        }
+        if let Some(etag) = stat.etag {
+            response.add_header("etag", etag);
+            let matches = if_none_match.is_some_and(|tags| tags.split(',').any(|tag| tag.trim() == etag || tag.trim() == "*"));
+            if matches && response.status == Status::Ok {
+                response.status = Status::NotModified;
+                response.body = Content::Empty;
+                return
+            }
+        }
+        if let Some(gzip) = stat.gzip {
+            response.add_header("vary", "accept-encoding");
+            if accepts_gzip {
+                response.add_header("content-encoding", "gzip");
+                response.body = Content::Static(gzip);
+            }
+        }
+    }
 } 
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, Default)]
 #[derive(PartialEq)]
 pub enum Method {
+    #[default]
     UNINITIALIZED,
     CONNECT,
     DELETE,
@@ -169,16 +364,39 @@ impl Method {
     pub fn parse(s: &str) -> Option<Method> {
         //REF: https://www.rfc-editor.org/rfc/rfc9112.html#section-3.1-1
         match s {
-            "GET"   => Some(Method::GET),
-            "POST"  => Some(Method::POST),
-            _       => None,
+            "CONNECT"   => Some(Method::CONNECT),
+            "DELETE"    => Some(Method::DELETE),
+            "GET"       => Some(Method::GET),
+            "HEAD"      => Some(Method::HEAD),
+            "OPTIONS"   => Some(Method::OPTIONS),
+            "PATCH"     => Some(Method::PATCH),
+            "POST"      => Some(Method::POST),
+            "PUT"       => Some(Method::PUT),
+            "TRACE"     => Some(Method::TRACE),
+            _           => None,
+        }
+    }
+    pub fn to_str(&self) -> &str {
+        match self {
+            Method::UNINITIALIZED => "UNINITIALIZED",
+            Method::CONNECT => "CONNECT",
+            Method::DELETE => "DELETE",
+            Method::GET => "GET",
+            Method::HEAD => "HEAD",
+            Method::OPTIONS => "OPTIONS",
+            Method::PATCH => "PATCH",
+            Method::POST => "POST",
+            Method::PUT => "PUT",
+            Method::TRACE => "TRACE",
+            Method::ANY => "*",
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub enum Version {
     V_1_0,
+    #[default]
     V_1_1,
     V_2_0,
     V_3_0,
@@ -209,21 +427,117 @@ impl Version {
 #[derive(Debug)]
 pub struct Header(pub &'static str, pub String);
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct Request {
     pub method: Method,
     pub path: Utf8PathBuf,
     pub version: Version,
     pub headers: HashMap<String, String>,
-    pub query_params: HashMap<String, String>,
+    /// the raw query string, without the `?`
+    pub query: String,
+    pub query_params: QueryParams,
+    /// captured by the route's `:name` and `*name` segments
+    pub params: HashMap<String, String>,
     pub body: Vec<u8>,
+    /// filled in by the server once the request is read
+    pub peer: Option<SocketAddr>,
+    /// whether it came in over tls
+    pub secure: bool,
+    /// counts up from 0 for every request the server handles, for telling them apart in logs
+    pub id: u64,
+    pub form_limits: Rc<FormLimits>,
+    pub state: Rc<AppState>,
+    /// a multipart body the server already parsed while it came in, for `form::Multipart`
+    pub(crate) multipart: Option<Result<Multipart, FormError>>,
+}
+
+/// decoded query parameters in the order they came in, repeats and all.
+/// a parameter without `=` has no value, as opposed to an empty one
+#[derive(Debug, Default, Clone)]
+pub struct QueryParams(pub Vec<(String, Option<String>)>);
+
+impl QueryParams {
+    /// the first value given for `key`, empty if it had none
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_deref().unwrap_or(""))
+    }
+    pub fn get_all<'q>(&'q self, key: &'q str) -> impl Iterator<Item = &'q str> + 'q {
+        self.0.iter()
+            .filter(move |(k, _)| k == key)
+            .map(|(_, value)| value.as_deref().unwrap_or(""))
+    }
+    pub fn contains(&self, key: &str) -> bool {
+        self.0.iter().any(|(k, _)| k == key)
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (&str, Option<&str>)> {
+        self.0.iter().map(|(key, value)| (key.as_str(), value.as_deref()))
+    }
+}
+
+/// a response that sends the client elsewhere, without a body.
+/// relative locations are made absolute against the request's `Host`
+#[derive(Debug, Clone)]
+pub struct Redirect {
+    pub status: Status,
+    pub location: String,
+}
+
+impl Redirect {
+    /// 301, clients may turn a POST into a GET
+    pub fn moved_permanently(location: &str) -> Self {
+        Self { status: Status::MovedPermanently, location: location.to_owned() }
+    }
+    /// 302, clients may turn a POST into a GET
+    pub fn found(location: &str) -> Self {
+        Self { status: Status::Found, location: location.to_owned() }
+    }
+    /// 303, the follow up request is always a GET, for after a form is posted
+    pub fn see_other(location: &str) -> Self {
+        Self { status: Status::SeeOther, location: location.to_owned() }
+    }
+    /// 307, the follow up request keeps the method and body
+    pub fn temporary(location: &str) -> Self {
+        Self { status: Status::TemporaryRedirect, location: location.to_owned() }
+    }
+    /// 308, the follow up request keeps the method and body
+    pub fn permanent(location: &str) -> Self {
+        Self { status: Status::PermanentRedirect, location: location.to_owned() }
+    }
+}
+
+pub enum ErrorPage {
+    /// a path into the vfs
+    Page(Utf8PathBuf),
+    Generator(Box<dyn FnMut(Status) -> Response>),
+}
+
+/// a bare bones page for statuses without one of their own, so there's always something to send
+pub fn fallback_page(status: Status) -> Response {
+    let line = status.to_status_line();
+    let page = format!("<!doctype html>\n<html>\n<head><title>{line}</title></head>\n<body><h1>{line}</h1></body>\n</html>\n");
+    let mut response: Response = status.into();
+    response.add_header("content-type", "text/html; charset=utf-8");
+    response.body = Content::Bytes(page.into_bytes());
+    response
 }
 
 pub struct Response {
     pub version: Version,
     pub status: Status,
     pub headers: Vec<Header>,
-    pub body: Utf8PathBuf,
+    pub body: Content,
+    /// set on a 101 by `HttpServer::add_websocket`, the connection is handed over once the head is serialized
+    pub upgrade: Option<Box<Upgrade>>,
+}
+
+pub enum Content {
+    /// a path into the vfs, the empty path stands for the homepage
+    Path(Utf8PathBuf),
+    Bytes(Vec<u8>),
+    Static(&'static [u8]),
+    Empty,
 }
 
 impl Response {
@@ -233,7 +547,8 @@ impl Response {
 }
 
 #[repr(i16)]
-#[derive(PartialEq)]
+#[derive(Debug, Clone, Copy)]
+#[derive(PartialEq, Eq, Hash)]
 pub enum Status {
     //Informational Responses (1XX)
         Continue,
@@ -268,6 +583,7 @@ pub enum Status {
         PaymentRequired,
         Forbidden,
         NotFound,
+        MethodNotAllowed,
         NotAcceptable,
         ProxyAuthenticationRequired,
         RequestTimeout,
@@ -306,30 +622,92 @@ pub enum Status {
 }
 
 impl Status {
+    pub fn code(&self) -> u16 {
+        self.to_status_line()[..3].parse().unwrap()
+    }
+    pub fn is_error(&self) -> bool {
+        self.code() >= 400
+    }
     pub fn to_status_line(&self) -> &str {
+        //REF: https://www.rfc-editor.org/rfc/rfc9110.html#section-15
         match self {
+            Self::Continue => "100 Continue",
+            Self::SwitchingProtocols => "101 Switching Protocols",
+            Self::EarlyHints => "103 Early Hints",
+
             Self::Ok => "200 OK",
             Self::Created => "201 Created",
+            Self::Accepted => "202 Accepted",
+            Self::NonAuthoritativeInformation => "203 Non-Authoritative Information",
+            Self::NoContent => "204 No Content",
+            Self::ResetContent => "205 Reset Content",
+            Self::PartialContent => "206 Partial Content",
+            Self::MultiStatus => "207 Multi-Status",
+            Self::AlreadyReported => "208 Already Reported",
+            Self::IMUsed => "226 IM Used",
+
+            Self::MultipleChoices => "300 Multiple Choices",
+            Self::MovedPermanently => "301 Moved Permanently",
+            Self::Found => "302 Found",
+            Self::SeeOther => "303 See Other",
             Self::NotModified => "304 Not Modified",
+            Self::Unused => "306 Unused",
+            Self::TemporaryRedirect => "307 Temporary Redirect",
+            Self::PermanentRedirect => "308 Permanent Redirect",
+
+            Self::BadRequest => "400 Bad Request",
+            Self::Unauthorized => "401 Unauthorized",
+            Self::PaymentRequired => "402 Payment Required",
+            Self::Forbidden => "403 Forbidden",
             Self::NotFound => "404 Not Found",
-            Self::SwitchingProtocols => "101 Switching Protocols",
-            _ => "STATUS CODE NOT IMPLEMENTED"
+            Self::MethodNotAllowed => "405 Method Not Allowed",
+            Self::NotAcceptable => "406 Not Acceptable",
+            Self::ProxyAuthenticationRequired => "407 Proxy Authentication Required",
+            Self::RequestTimeout => "408 Request Timeout",
+            Self::Conflict => "409 Conflict",
+            Self::Gone => "410 Gone",
+            Self::LengthRequired => "411 Length Required",
+            Self::PreconditionFailed => "412 Precondition Failed",
+            Self::ContentTooLarge => "413 Content Too Large",
+            Self::URITooLong => "414 URI Too Long",
+            Self::UnsupportedMediaType => "415 Unsupported Media Type",
+            Self::RangeNotSatisfiable => "416 Range Not Satisfiable",
+            Self::ExpectationFailed => "417 Expectation Failed",
+            Self::ImaTeapot => "418 I'm a teapot",
+            Self::MisdirectedRequest => "421 Misdirected Request",
+            Self::UnprocessableContent => "422 Unprocessable Content",
+            Self::Locked => "423 Locked",
+            Self::FailedDependency => "424 Failed Dependency",
+            Self::TooEarly => "425 Too Early",
+            Self::UpgradeRequired => "426 Upgrade Required",
+            Self::PreconditionRequired => "428 Precondition Required",
+            Self::TooManyRequests => "429 Too Many Requests",
+            Self::RequestHeaderFieldTooLarge => "431 Request Header Fields Too Large",
+            Self::UnavailableForLegalReasons => "451 Unavailable For Legal Reasons",
+
+            Self::InternalServerError => "500 Internal Server Error",
+            Self::NotImplemented => "501 Not Implemented",
+            Self::BadGateway => "502 Bad Gateway",
+            Self::ServiceUnavailable => "503 Service Unavailable",
+            Self::GatewayTimeout => "504 Gateway Timeout",
+            Self::HTTPVersionNotSupported => "505 HTTP Version Not Supported",
+            Self::VariantAlsoNegotiates => "506 Variant Also Negotiates",
+            Self::InsufficientStorage => "507 Insufficient Storage",
+            Self::LoopDetected => "508 Loop Detected",
+            Self::NotExtended => "510 Not Extended",
+            Self::NetworkAuthenticationRequired => "511 Network Authentication Required",
         }
     }
 }
 
-pub struct Handler<I, O, F>(pub F, PhantomData<I>, PhantomData<O>)
+pub struct Handler<Args, O, F>(pub F, PhantomData<Args>, PhantomData<O>)
 where
-    I: From<Request>,
-    O: Into<Response>,
-    F: FnMut(I) -> O,
+    F: Endpoint<Args, O>,
 ;
 
-impl<I, O, F> Handler<I, O, F> 
+impl<Args, O, F> Handler<Args, O, F> 
 where
-    I: From<Request>,
-    O: Into<Response>,
-    F: FnMut(I) -> O,
+    F: Endpoint<Args, O>,
 {
     pub fn new(function: F) -> Self {
         Self(function, Default::default(), Default::default())
@@ -340,13 +718,45 @@ pub trait Handle {
     fn handle(&mut self, r: Request) -> Response;
 }
 
-impl<I, O, F> Handle for Handler<I, O, F>
+impl<Args, O, F> Handle for Handler<Args, O, F>
 where
-    I: From<Request>,
-    O: Into<Response>,
-    F: FnMut(I) -> O,
+    F: Endpoint<Args, O>,
 {
     fn handle(&mut self, r: Request) -> Response {
-        (self.0)(I::from(r)).into()
+        self.0.call(r)
     }
-}
\ No newline at end of file
+}
+
+/// a function whose arguments can all be extracted from a request
+pub trait Endpoint<Args, O> {
+    fn call(&mut self, r: Request) -> Response;
+}
+
+macro_rules! impl_endpoint {
+    ($($arg:ident),*) => {
+        impl<$($arg,)* O, F> Endpoint<($($arg,)*), O> for F
+        where
+            $($arg: FromRequest,)*
+            O: Into<Response>,
+            F: FnMut($($arg),*) -> O,
+        {
+            #[allow(unused_mut, unused_variables)]
+            fn call(&mut self, mut r: Request) -> Response {
+                $(
+                    let $arg = match $arg::from_request(&mut r) {
+                        Ok(arg) => arg,
+                        Err(rejection) => return rejection,
+                    };
+                )*
+                (self)($($arg),*).into()
+            }
+        }
+    };
+}
+impl_endpoint!();
+impl_endpoint!(A);
+impl_endpoint!(A, B);
+impl_endpoint!(A, B, C);
+impl_endpoint!(A, B, C, D);
+impl_endpoint!(A, B, C, D, E);
+impl_endpoint!(A, B, C, D, E, G);
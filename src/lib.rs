@@ -8,26 +8,38 @@ pub mod helpers;
 pub mod websocket;
 pub mod TLS;
 pub mod server_G;
-
-use std::collections::HashMap;
+pub mod vfs;
+pub mod embed;
+pub mod router;
+pub mod extract;
+pub mod form;
+pub mod middleware;
+pub mod state;
+
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
 use std::io::{self};
-use core::str;
+use core::{fmt, str};
 use std::io::{Write, ErrorKind};
-use std::io::Read;
-use std::fs::{self, read_dir};
+use std::io::{Read, Seek, SeekFrom};
+use std::fs;
+use std::mem;
 use std::net::SocketAddr;
 use std::rc::Rc;
 use std::sync::Arc;
 use std::time::{Duration, Instant, SystemTime};
 
-use helpers::{throw_reader_at_writer, SendTo};
-use http::HttpServer;
+use chrono::{DateTime, Utc};
+use helpers::{escape_html, escape_json, percent_encode, throw_reader_at_writer, SendTo};
+use form::{FormError, MultipartReader};
+use http::{Content, HttpServer, Method, Request, Response, Status};
 use mio::net::{TcpListener, TcpStream};
 use mio::{Events, Interest, Poll, Token};
 
 use camino::{Utf8Path, Utf8PathBuf};
 use rustls::ServerConfig;
 use smithy::{HttpSmith, ParseError};
+use vfs::{Directory, V_stat, VfsBackend};
 use TLS::TLStream;
 
 // pub type HttpServer2 = Server_G<Package, HttpSmithText, Request, ParseError>;
@@ -40,7 +52,8 @@ pub struct Server {
     pub clients: HashMap<StreamId, Client>,
     pub http: HttpServer,
 
-    pub config: Arc<ServerConfig>,
+    /// None when serving plain http
+    pub config: Option<Arc<ServerConfig>>,
     pub listener: TcpListener,
     pub poll: Poll,
     pub last_refresh: Instant,
@@ -49,19 +62,26 @@ pub struct Server {
 
 impl Server {
     pub fn new(address: SocketAddr, config: ServerConfig) -> Self {
+        Self::bind(address, Some(Arc::new(config)))
+    }
+    pub fn new_plain(address: SocketAddr) -> Self {
+        Self::bind(address, None)
+    }
+    fn bind(address: SocketAddr, config: Option<Arc<ServerConfig>>) -> Self {
         let poll = Poll::new().unwrap();
         let registry = poll.registry();
         let mut listener = TcpListener::bind(address).unwrap();
 
         registry.register(&mut listener, Token(SERVER), Interest::READABLE | Interest::WRITABLE).unwrap();
 
-        println!("HTTPSERVER: initializing server on (https://{}:{})", address.ip(), address.port());
+        let scheme = if config.is_some() { "https" } else { "http" };
+        println!("HTTPSERVER: initializing server on ({scheme}://{}:{})", address.ip(), address.port());
 
         Self { 
             clients: HashMap::with_capacity(1028),
             http: HttpServer::new(),
 
-            config: Arc::new(config),
+            config,
             listener,
             poll,
             last_refresh: Instant::now(),
@@ -94,7 +114,7 @@ impl Server {
                     _client => {
                         let client = self.clients.get_mut(&id).unwrap();
                         let stream = &mut client.stream;
-                        if stream.tls.is_handshaking() {
+                        if stream.is_handshaking() {
                             match client.stream.handshake() {
                                 Ok(_) => {},
                                 Err(e) if e.kind() == ErrorKind::WouldBlock => {},
@@ -102,10 +122,13 @@ impl Server {
                                 Err(e) => {
                                     println!("TLServer: dropped client on account of error when handshaking: {e}");
                                     self.drop_client(id);
+                                    continue
                                 },
                             };
-                            continue
+                            // the request may have come in with the end of the handshake
+                            if client.stream.is_handshaking() { continue }
                         }
+                        let stream = &mut client.stream;
                         if event.is_writable() {
                             match stream.flush() {
                                 Ok(_) => {},
@@ -120,7 +143,7 @@ impl Server {
                                     break
                                 },
                             } 
-                            match client.delivery.send_all(stream) {
+                            match client.delivery.deliver(stream) {
                                 Ok(_) => {},
                                 Err(e) if e.kind() == ErrorKind::WouldBlock => {},
                                 Err(e) => {
@@ -141,23 +164,40 @@ impl Server {
                                 }
                             };
                             if !client.buf.has_read() { continue }
-                            let story = client.buf.the_story_so_far();
-                            match self.http.smith.deserialize(story) {
-                                Ok((request, _rest)) => {
-                                    client.buf.data.clear();
-                                    client.buf.read = client.buf.data.len();
-                                    client.buf.prev_read = client.buf.read;
-
-                                    let response = self.http.handle_request(request);
-                                    let (header, body_path) = self.http.smith.serialize(&response);
-                                    //TODO: this panics if u haven't set a 404 page
-                                    println!("body_path = {body_path}");
+                            match read_request(&self.http, &mut client.buf, &mut client.incoming) {
+                                Ok(Some((mut request, leftover))) => {
+                                    request.peer = stream.tcp.peer_addr().ok();
+                                    request.secure = !stream.is_plain();
+                                    let head_only = request.method == Method::HEAD;
+                                    let mut response = self.http.handle_request(request);
+                                    if let Some(upgrade) = response.upgrade.take() {
+                                        let head = self.http.smith.serialize(&response);
+                                        let mut client = self.clients.remove(&id).unwrap();
+                                        self.poll.registry().deregister(&mut client.stream).unwrap();
+                                        if !upgrade.hand_over(client.stream, head, leftover) {
+                                            println!("HTTP_SERVER: dropped client {id}, the websocket server is gone");
+                                        }
+                                        continue
+                                    }
+                                    let body = match into_body(&mut self.http.file_system, &mut response) {
+                                        Some(body) => body,
+                                        None => {
+                                            println!("HTTP_SERVER: a file went missing from the vfs, answering with a 500 instead");
+                                            response = self.http.fallback_response(Status::InternalServerError);
+                                            into_body(&mut self.http.file_system, &mut response).unwrap_or_default()
+                                        }
+                                    };
+                                    // the content-length is still the one a GET would get
+                                    let body = match head_only {
+                                        true => Body::Static(&[]),
+                                        false => body,
+                                    };
                                     client.delivery = Package {
-                                        head: header,
-                                        body: self.http.file_system.get(&body_path).unwrap().clone(),
+                                        head: self.http.smith.serialize(&response),
+                                        body,
                                         writ: 0,
                                     };
-                                    match client.delivery.send_all(stream) {
+                                    match client.delivery.deliver(stream) {
                                         Ok(_) => {},
                                         Err(e) if e.kind() == ErrorKind::WouldBlock => {},
                                         Err(e) => {
@@ -167,9 +207,13 @@ impl Server {
                                         }
                                     };
                                 },
-                                Err(ParseError::Incomplete) => println!("HTTP_SERVER: request incomplete at size {}", story.len()),
-                                Err(e) => {
-                                    println!("HTTP_SERVER: dropped client on account of error when parsing request: {e}");
+                                Ok(None) => {},
+                                Err(status) => {
+                                    // one last try at telling the client what went wrong, the connection can't be trusted after this
+                                    let mut response = self.http.error_response(status);
+                                    let body = into_body(&mut self.http.file_system, &mut response).unwrap_or_default();
+                                    let mut farewell = Package { head: self.http.smith.serialize(&response), body, writ: 0 };
+                                    let _ = farewell.deliver(stream);
                                     self.drop_client(id);
                                     break
                                 }
@@ -179,7 +223,7 @@ impl Server {
                     }
                 }
             }
-            if self.last_refresh.elapsed() > Duration::from_millis(800) {
+            if self.last_refresh.elapsed() > Duration::from_millis(800) && self.http.file_system.client_dir != "" {
                 let changelog = self.http.file_system.client_dir.join(".changelog");
                 match fs::metadata(&changelog) {
                     Ok(md) => {
@@ -217,7 +261,10 @@ impl Server {
     }
     fn register(&mut self, client: TcpStream) -> io::Result<StreamId> {
         let registry = self.poll.registry();
-        let mut stream = TLStream::new(client, self.config.clone());
+        let mut stream = match &self.config {
+            Some(config) => TLStream::new(client, config.clone()),
+            None => TLStream::plain(client),
+        };
         let mut id = fastrand::usize(..);
         while self.clients.contains_key(&id) {
             id = fastrand::usize(..);
@@ -234,39 +281,269 @@ impl Server {
 }
 
 
-#[derive(Clone)]
+/// the next whole request off the client's buffer and whatever came after it, None until it's all in.
+/// A body over `HttpServer::max_body`, or a form over `FormLimits::max_total`, is refused from the head alone.
+/// A multipart one is parsed as it arrives
+fn read_request(http: &HttpServer, buf: &mut Buffer, incoming: &mut Option<Incoming>) -> Result<Option<(Request, Vec<u8>)>, Status> {
+    if incoming.is_none() {
+        let story = buf.the_story_so_far();
+        let (request, length, rest) = match http.smith.deserialize_head(story) {
+            Ok(head) => head,
+            Err(ParseError::Incomplete) => {
+                println!("HTTP_SERVER: request incomplete at size {}", story.len());
+                return Ok(None)
+            }
+            Err(e) => {
+                println!("HTTP_SERVER: dropped client on account of error when parsing request: {e}");
+                return Err(Status::BadRequest)
+            }
+        };
+        let content_type = request.headers.get("content-type").map(String::as_str).unwrap_or("");
+        let mime = content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+        let is_form = mime == "multipart/form-data" || mime == "application/x-www-form-urlencoded";
+        if is_form && length > http.form_limits.max_total {
+            println!("HTTP_SERVER: dropped client, it wanted to send a {length} byte form");
+            return Err(Status::ContentTooLarge)
+        }
+        if !is_form && length > http.max_body {
+            println!("HTTP_SERVER: dropped client, it wanted to send a {length} byte body");
+            return Err(Status::ContentTooLarge)
+        }
+        let multipart = match mime == "multipart/form-data" {
+            true => Some(MultipartReader::new(content_type, http.form_limits.clone())),
+            false => None,
+        };
+        let head = story.len() - rest.len();
+        *incoming = Some(Incoming { request, remaining: length, multipart });
+        buf.consume(head);
+    }
+
+    let pending = incoming.as_mut().unwrap();
+    let story = buf.the_story_so_far();
+    let body = &story[..pending.remaining.min(story.len())];
+    match &mut pending.multipart {
+        Some(Ok(reader)) => if let Err(e) = reader.feed(body) {
+            // the rest of the body is still read, but only to be thrown away
+            pending.multipart = Some(Err(e));
+        },
+        Some(Err(_)) => {},
+        None => pending.request.body.extend_from_slice(body),
+    }
+    pending.remaining -= body.len();
+    buf.consume(body.len());
+    if pending.remaining > 0 { return Ok(None) }
+
+    let Incoming { mut request, multipart, .. } = incoming.take().unwrap();
+    request.multipart = multipart.map(|parsed| parsed.and_then(MultipartReader::finish));
+    let leftover = buf.the_story_so_far().to_vec();
+    buf.consume(leftover.len());
+    Ok(Some((request, leftover)))
+}
+
+/// None if the response points at a file the vfs can't produce
+fn into_body(file_system: &mut Vfs, response: &mut Response) -> Option<Body> {
+    match mem::replace(&mut response.body, Content::Empty) {
+        Content::Path(path) => file_system.get(&path),
+        Content::Bytes(data) => Some(Body::Cached(V_file { data }.into())),
+        Content::Static(data) => Some(Body::Static(data)),
+        Content::Empty => Some(Body::default()),
+    }
+}
+
 pub struct Vfs {
-    files: HashMap<Utf8PathBuf, Rc<V_file>>,
+    mounts: Vec<Mount>,
     client_dir: Utf8PathBuf,
+    pub autoindex: Option<AutoIndex>,
+    pub budget: usize,
+    pub stream_threshold: usize,
+}
+
+pub struct Mount {
+    pub prefix: Utf8PathBuf,
+    pub backend: Box<dyn VfsBackend>,
+    pub autoindex: Option<AutoIndex>,
 }
 
 impl Vfs {
-    pub fn get(&self, path: &Utf8Path) -> Option<Rc<V_file>> {
-        println!("attempted to get {path}, result = {}", self.files.contains_key(path));
-        self.files.get(path).cloned()
+    pub fn new() -> Self {
+        Self {
+            mounts: Vec::new(),
+            client_dir: Utf8PathBuf::new(),
+            autoindex: None,
+            budget: 64 << 20,
+            stream_threshold: 4 << 20,
+        }
     }
 
-    pub fn remove(&mut self, path: &Utf8Path) {
-        if self.files.remove(path).is_none() {
-            println!("FILE_SYSTEM: attemped to uncache already uncached file. @suspicious")
+    pub fn mount(&mut self, prefix: &str, backend: impl VfsBackend + 'static) -> &mut Mount {
+        self.mounts.push(Mount {
+            prefix: prefix.trim_matches('/').into(),
+            backend: Box::new(backend),
+            autoindex: None,
+        });
+        self.mounts.last_mut().unwrap()
+    }
+
+    pub(crate) fn init(&mut self) {
+        // the client directory sits underneath everything else mounted at the root
+        if self.client_dir != "" {
+            let mut dir = Directory::new(self.client_dir.clone());
+            dir.budget = self.budget;
+            dir.stream_threshold = self.stream_threshold;
+            self.mounts.insert(0, Mount {
+                prefix: Utf8PathBuf::new(),
+                backend: Box::new(dir),
+                autoindex: self.autoindex,
+            });
+        }
+        for mount in &mut self.mounts {
+            mount.backend.init();
         }
     }
-    
-    fn sync_with_file_system(&mut self, path: &Utf8Path) {
-        let sys_path = self.client_dir.join(path);
-        match fs::read(&sys_path) {
-            Ok(data) => {
-                println!("FILE_SYSTEM: new pair with key = {path}, value from {sys_path}");
-                self.files.insert(path.into(), V_file { data }.into());
+
+    /// the mounts that could hold `path` along with the path relative to each, by precedence:
+    /// longer prefixes win, and among equal prefixes the most recent mount wins
+    fn candidates(&self, path: &Utf8Path) -> Vec<(usize, Utf8PathBuf)> {
+        let mut found: Vec<(usize, Utf8PathBuf)> = self.mounts.iter()
+            .enumerate()
+            .filter_map(|(index, mount)| Some((index, path.strip_prefix(&mount.prefix).ok()?.to_owned())))
+            .collect();
+        found.sort_by_key(|(index, _)| Reverse((self.mounts[*index].prefix.components().count(), *index)));
+        found
+    }
+
+    pub fn stat(&self, path: &Utf8Path) -> Option<V_stat> {
+        for (index, relative) in self.candidates(path) {
+            if let Some(stat) = self.mounts[index].backend.stat(&relative) {
+                return Some(stat)
             }
-            Err(e) if e.kind() == ErrorKind::NotFound => {
-                println!("FILE_SYSTEM: attemped to sync with nonexistant file at {sys_path}. @suspicious");
+        }
+        // directories leading up to a mount point exist even if no backend has them
+        if self.mounts.iter().any(|mount| mount.prefix.starts_with(path)) {
+            return Some(V_stat::dir())
+        }
+        None
+    }
+
+    pub fn get(&mut self, path: &Utf8Path) -> Option<Body> {
+        for (index, relative) in self.candidates(path) {
+            let backend = &mut self.mounts[index].backend;
+            if backend.stat(&relative).is_some_and(|stat| !stat.is_dir) {
+                return backend.get(&relative)
             }
-            Err(e) => {
-                println!("FILE_SYSTEM: failed to read file at {sys_path} because of Error: {e}");
+        }
+        None
+    }
+
+    pub fn contains(&self, path: &Utf8Path) -> bool {
+        self.stat(path).is_some_and(|stat| !stat.is_dir)
+    }
+
+    pub fn is_dir(&self, path: &Utf8Path) -> bool {
+        self.stat(path).is_some_and(|stat| stat.is_dir)
+    }
+
+    pub fn get_size(&self, path: &Utf8Path) -> Option<usize> {
+        self.stat(path).filter(|stat| !stat.is_dir).map(|stat| stat.size)
+    }
+
+    pub fn index_of(&self, dir: &Utf8Path) -> Option<Utf8PathBuf> {
+        let index = dir.join("index.html");
+        self.contains(&index).then_some(index)
+    }
+
+    /// the autoindex setting of the mount that owns `dir`
+    pub fn autoindex_for(&self, dir: &Utf8Path) -> Option<AutoIndex> {
+        let candidates = self.candidates(dir);
+        let owner = candidates.iter()
+            .find(|(index, relative)| self.mounts[*index].backend.stat(relative).is_some())
+            .or(candidates.first())?;
+        self.mounts[owner.0].autoindex
+    }
+
+    /// the direct children of a directory across every mount, sorted by name
+    pub fn list(&self, dir: &Utf8Path) -> Vec<V_listing> {
+        let mut listing: Vec<V_listing> = Vec::new();
+        let mut seen = HashSet::new();
+        for (index, relative) in self.candidates(dir) {
+            for entry in self.mounts[index].backend.list(&relative) {
+                if seen.insert(entry.name.clone()) {
+                    listing.push(entry);
+                }
+            }
+        }
+        for mount in &self.mounts {
+            let Some(name) = mount.prefix.file_name() else { continue };
+            if mount.prefix.parent() == Some(dir) && seen.insert(name.to_owned()) {
+                listing.push(V_listing { name: name.to_owned(), is_dir: true, size: 0, modified: SystemTime::UNIX_EPOCH });
+            }
+        }
+        listing.sort_by(|a, b| a.name.cmp(&b.name));
+        listing
+    }
+
+    /// returns the listing along with its content-type
+    pub fn render_listing(&self, dir: &Utf8Path, target: &Utf8Path, format: AutoIndex) -> (Vec<u8>, &'static str) {
+        let listing = self.list(dir);
+        match format {
+            AutoIndex::Html => {
+                let title = escape_html(target.as_str());
+                let mut html = format!(
+                    "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Index of {title}</title></head>\n<body>\n<h1>Index of {title}</h1>\n<table>\n<tr><th>Name</th><th>Size</th><th>Last Modified</th></tr>\n"
+                );
+                if dir != "" {
+                    html.push_str("<tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>\n");
+                }
+                for entry in listing {
+                    let slash = if entry.is_dir { "/" } else { "" };
+                    let size = if entry.is_dir { "-".to_owned() } else { entry.size.to_string() };
+                    let modified: DateTime<Utc> = entry.modified.into();
+                    html.push_str(&format!(
+                        "<tr><td><a href=\"{}{slash}\">{}{slash}</a></td><td>{size}</td><td>{}</td></tr>\n",
+                        percent_encode(&entry.name),
+                        escape_html(&entry.name),
+                        modified.format("%Y-%m-%d %H:%M"),
+                    ));
+                }
+                html.push_str("</table>\n</body>\n</html>\n");
+                (html.into_bytes(), "text/html; charset=utf-8")
+            }
+            AutoIndex::Json => {
+                let entries: Vec<String> = listing.iter()
+                    .map(|entry| {
+                        let modified: DateTime<Utc> = entry.modified.into();
+                        format!(
+                            "{{\"name\":\"{}\",\"type\":\"{}\",\"size\":{},\"modified\":\"{}\"}}",
+                            escape_json(&entry.name),
+                            if entry.is_dir { "directory" } else { "file" },
+                            entry.size,
+                            modified.to_rfc3339(),
+                        )
+                    })
+                    .collect();
+                (format!("[{}]", entries.join(",")).into_bytes(), "application/json")
             }
         }
     }
+    
+    fn client_mount(&mut self) -> Option<&mut Mount> {
+        match self.client_dir.as_str() {
+            "" => None,
+            _ => self.mounts.first_mut(),
+        }
+    }
+
+    pub fn remove(&mut self, path: &Utf8Path) {
+        if let Some(mount) = self.client_mount() {
+            mount.backend.remove(path);
+        }
+    }
+    
+    fn sync_with_file_system(&mut self, path: &Utf8Path) {
+        if let Some(mount) = self.client_mount() {
+            mount.backend.sync(path);
+        }
+    }
 
     pub fn apply_diff(&mut self, diff: &str) {
         if let Some(path) = diff.strip_prefix("DELETE\t") {
@@ -278,53 +555,159 @@ impl Vfs {
         }
     }
 
-    pub fn get_size(&mut self, path: &Utf8Path) -> Option<usize> {
-        self.get(path).map(|file| file.data.len())
+    pub fn stats(&self) -> VfsStats {
+        let mut total = VfsStats::default();
+        for mount in &self.mounts {
+            let stats = mount.backend.stats();
+            total.hits += stats.hits;
+            total.misses += stats.misses;
+            total.evictions += stats.evictions;
+            total.streamed += stats.streamed;
+            total.cached += stats.cached;
+        }
+        total
     }
+}
 
-    fn build_cache(&mut self) {
-        self._build_cache("");
+impl Default for Vfs {
+    fn default() -> Self {
+        Self::new()
     }
-    fn _build_cache(&mut self, dir: impl AsRef<Utf8Path>) {
-        let d = dir.as_ref();
-        let iter = read_dir(self.client_dir.join(d)).unwrap();
-        for f in iter.flatten() {
-            let f_ = f.file_name();
-            let name = f_.to_str().unwrap();
+}
 
-            // we ignore dotfiles
-            if name.starts_with('.') {
-                continue
-            }
-            let path = d.join(name);
-            if f.file_type().unwrap().is_dir() {
-                self._build_cache(&path)
-            }
-            else {
-                self.sync_with_file_system(&path)
-            }
+#[derive(Debug, Clone)]
+pub struct V_listing {
+    pub name: String,
+    pub is_dir: bool,
+    pub size: usize,
+    pub modified: SystemTime,
+}
+
+impl V_listing {
+    fn new(path: &Utf8Path, stat: V_stat) -> Self {
+        Self {
+            name: path.file_name().unwrap_or_default().to_owned(),
+            is_dir: stat.is_dir,
+            size: stat.size,
+            modified: stat.modified,
+        }
     }
 }
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AutoIndex {
+    Html,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct VfsStats {
+    pub hits: usize,
+    pub misses: usize,
+    pub evictions: usize,
+    pub streamed: usize,
+    pub cached: usize,
 }
 
-#[derive(Debug, Clone, Default)]
+impl VfsStats {
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            total => self.hits as f64 / total as f64,
+        }
+    }
+}
+
+impl fmt::Display for VfsStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f, 
+            "hits = {}, misses = {}, hit rate = {:.1}%, evictions = {}, streamed = {}, cached bytes = {}",
+            self.hits, self.misses, self.hit_rate() * 100.0, self.evictions, self.streamed, self.cached,
+        )
+    }
+}
+
+#[derive(Debug, Default)]
 pub struct Package {
     head: Vec<u8>,
-    body: Rc<V_file>,
+    body: Body,
     writ: usize,
 }
 
+impl Package {
+    /// sends as much as possible, letting the kernel copy streamed files straight into plain tcp sockets
+    pub fn deliver(&mut self, stream: &mut TLStream) -> io::Result<usize> {
+        #[cfg(target_os = "linux")]
+        if let (true, Body::Streamed(_)) = (stream.is_plain(), &self.body) {
+            return self.sendfile(&stream.tcp)
+        }
+        self.send_all(stream)
+    }
+    #[cfg(target_os = "linux")]
+    fn sendfile(&mut self, tcp: &TcpStream) -> io::Result<usize> {
+        use std::os::fd::AsRawFd;
+
+        let mut total = 0;
+        while self.writ < self.head.len() {
+            match (&*tcp).write(&self.head[self.writ..]) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(writ) => {
+                    self.writ += writ;
+                    total += writ;
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        let Body::Streamed(stream) = &self.body else { unreachable!() };
+        loop {
+            let sent_so_far = (self.writ - self.head.len()) as u64;
+            let remaining = stream.len - sent_so_far;
+            if remaining == 0 { break }
+            let mut offset = (stream.start + sent_so_far) as libc::off_t;
+            let sent = unsafe {
+                libc::sendfile(tcp.as_raw_fd(), stream.file.as_raw_fd(), &mut offset, SENDFILE_CHUNK_SIZE.min(remaining as usize))
+            };
+            match sent {
+                // the file shrank since it was stat'ed, what's left of the content-length will never come
+                0 => return Err(io::Error::new(ErrorKind::UnexpectedEof, "the file got shorter while it was being sent")),
+                -1 => {
+                    let e = io::Error::last_os_error();
+                    if e.kind() == ErrorKind::Interrupted { continue }
+                    return Err(e)
+                }
+                sent => {
+                    self.writ += sent as usize;
+                    total += sent as usize;
+                }
+            }
+        }
+        Ok(total)
+    }
+}
+
 impl SendTo for Package {
     fn send_to(&mut self, wr: &mut impl Write) -> io::Result<usize> {
         let writ = self.writ;
-        let source = 
+        let read = 
             if writ < self.head.len() {
-                &self.head[writ..]
+                wr.write(&self.head[writ..])?
             }
             else {
-                &Rc::as_ref(&self.body).data[writ - self.head.len()..]
+                match &mut self.body {
+                    Body::Cached(file) => wr.write(&file.data[writ - self.head.len()..])?,
+                    Body::Static(data) => wr.write(&data[writ - self.head.len()..])?,
+                    Body::Streamed(stream) => {
+                        if stream.pos == stream.chunk.len() {
+                            stream.refill()?;
+                        }
+                        let read = wr.write(&stream.chunk[stream.pos..])?;
+                        stream.pos += read;
+                        read
+                    }
+                }
             };
-        let read = wr.write(source)?;
         
         self.writ += read;
         println!("wrote {read} bytes");
@@ -338,11 +721,68 @@ impl Read for Package {
     }
 }
 
+#[derive(Debug)]
+pub enum Body {
+    Cached(Rc<V_file>),
+    /// compiled into the binary, see `embed`
+    Static(&'static [u8]),
+    Streamed(V_stream),
+}
+
+impl Default for Body {
+    fn default() -> Self {
+        Self::Cached(Rc::default())
+    }
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct V_file {
     data: Vec<u8>,
 }
 
+const CHUNK_SIZE: usize = 64 << 10;
+#[cfg(target_os = "linux")]
+const SENDFILE_CHUNK_SIZE: usize = 1 << 20;
+
+/// a region of a file on disk that is sent in chunks rather than cached
+#[derive(Debug)]
+pub struct V_stream {
+    file: fs::File,
+    start: u64,
+    len: u64,
+    read: u64,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl V_stream {
+    fn region(path: &Utf8Path, start: u64, len: u64) -> io::Result<Self> {
+        let mut file = fs::File::open(path)?;
+        file.seek(SeekFrom::Start(start))?;
+        Ok(Self {
+            file,
+            start,
+            len,
+            read: 0,
+            chunk: Vec::new(),
+            pos: 0,
+        })
+    }
+    fn refill(&mut self) -> io::Result<()> {
+        let want = CHUNK_SIZE.min((self.len - self.read) as usize);
+        self.chunk.resize(want, 0);
+        let read = self.file.read(&mut self.chunk)?;
+        // the file shrank since it was stat'ed, the client would wait forever on the rest
+        if read == 0 && self.read < self.len {
+            return Err(io::Error::new(ErrorKind::UnexpectedEof, "the file got shorter while it was being sent"))
+        }
+        self.chunk.truncate(read);
+        self.read += read as u64;
+        self.pos = 0;
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 #[derive(Clone, Copy)]
 #[derive(PartialEq)]
@@ -379,7 +819,7 @@ impl Write for Buffer {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         self.prev_read = self.read;
 
-        let writ = self.data.write(&buf[self.read..])?;
+        let writ = self.data.write(buf)?;
         self.read += writ;
         //TODO: limit the growth of the buffer
 
@@ -397,6 +837,16 @@ pub struct Client {
     pub delivery: Package,
     pub buf: Buffer,
     pub protocol: Protocol,
+    /// a request whose head is in but whose body is still coming
+    incoming: Option<Incoming>,
+}
+
+struct Incoming {
+    request: Request,
+    /// body bytes yet to come
+    remaining: usize,
+    /// a multipart body goes here rather than into `request.body`
+    multipart: Option<Result<MultipartReader, FormError>>,
 }
 
 impl Client {
@@ -407,6 +857,7 @@ impl Client {
             delivery: Package::default(),
             buf: Buffer::with_capacity(4096), //TODO: maybe this should be less aligned?
             protocol,
+            incoming: None,
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,53 @@
+//! Layers wrapped around request handling.
+//!
+//! A middleware gets the request along with `next`, the rest of the chain. It can change the request
+//! before passing it on, answer by itself without calling `next`, or change the response it gets back:
+//! ```ignore
+//! server.http.add_layer(|request: Request, next: Next| {
+//!     let start = Instant::now();
+//!     let target = format!("{:?} {}", request.method, request.path);
+//!     let response = next.run(request);
+//!     println!("HTTP_SERVER: {target} took {:?}", start.elapsed());
+//!     response
+//! });
+//! server.http.add_service("/admin/*path", Method::GET, serve_admin)
+//!     .layer(|request: Request, next: Next| match request.headers.contains_key("authorization") {
+//!         true => next.run(request),
+//!         false => Status::Unauthorized.into(),
+//!     });
+//! ```
+//! Layers added with `HttpServer::add_layer` run for every request, before routing, so they can rewrite the target.
+//! Layers on a route run after routing, so they see the captured params.
+
+use crate::http::{Request, Response};
+
+pub trait Middleware {
+    fn call(&mut self, request: Request, next: Next<'_>) -> Response;
+}
+
+impl<F> Middleware for F
+where
+    F: FnMut(Request, Next<'_>) -> Response,
+{
+    fn call(&mut self, request: Request, next: Next<'_>) -> Response {
+        self(request, next)
+    }
+}
+
+/// the layers still to run and whatever they're wrapped around
+pub struct Next<'a> {
+    layers: &'a mut [Box<dyn Middleware>],
+    inner: &'a mut dyn FnMut(Request) -> Response,
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(layers: &'a mut [Box<dyn Middleware>], inner: &'a mut dyn FnMut(Request) -> Response) -> Self {
+        Self { layers, inner }
+    }
+    pub fn run(self, request: Request) -> Response {
+        match self.layers.split_first_mut() {
+            Some((layer, rest)) => layer.call(request, Next::new(rest, self.inner)),
+            None => (self.inner)(request),
+        }
+    }
+}
@@ -0,0 +1,250 @@
+//! Segment tree of routes.
+//!
+//! Patterns are split on `/`, each segment is either
+//! - a literal, `users`
+//! - a parameter, `:id`, which captures exactly one segment
+//! - a wildcard, `*rest`, which captures everything after it (possibly nothing), only allowed last
+//!
+//! Lookup prefers literals over parameters over wildcards, backtracking when a branch dead ends,
+//! so registration order doesn't matter.
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::process::exit;
+
+use crate::http::{Handle, Method, Request, Response, Status};
+use crate::middleware::{Middleware, Next};
+
+#[derive(Default)]
+pub struct Router {
+    root: Node,
+    services: Vec<Service>,
+}
+
+/// a handler and the layers wrapped around it
+pub struct Service {
+    pattern: String,
+    method: Method,
+    handler: Box<dyn Handle>,
+    layers: Vec<Box<dyn Middleware>>,
+    panics: u32,
+    fail_after: Option<u32>,
+}
+
+impl Service {
+    /// runs `middleware` around this route only, in the order they're added
+    pub fn layer(&mut self, middleware: impl Middleware + 'static) -> &mut Self {
+        self.layers.push(Box::new(middleware));
+        self
+    }
+    /// after `panics` panics the route stops calling its handler and answers 503 instead
+    pub fn fail_after(&mut self, panics: u32) -> &mut Self {
+        self.fail_after = Some(panics);
+        self
+    }
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+    pub fn is_failing(&self) -> bool {
+        self.fail_after.is_some_and(|limit| self.panics >= limit)
+    }
+    pub fn panics(&self) -> u32 {
+        self.panics
+    }
+    pub(crate) fn handle(&mut self, request: Request) -> Response {
+        if self.is_failing() {
+            return Status::ServiceUnavailable.into()
+        }
+        let id = request.id;
+        let method = request.method;
+        let handler = &mut self.handler;
+        let layers = &mut self.layers;
+
+        // handlers own nothing of ours that a panic could leave half updated
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            Next::new(layers, &mut |request| handler.handle(request)).run(request)
+        }));
+        match result {
+            Ok(response) => response,
+            Err(payload) => {
+                self.panics += 1;
+                println!("HTTP_SERVER: request {id} panicked in [{} {}]: {}", method.to_str(), self.pattern, panic_message(&*payload));
+                if self.is_failing() {
+                    println!("HTTP_SERVER: [{} {}] panicked {} times, marking it as failing", method.to_str(), self.pattern, self.panics);
+                }
+                Status::InternalServerError.into()
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct Node {
+    literals: HashMap<String, Node>,
+    param: Option<(String, Box<Node>)>,
+    wildcard: Option<(String, Vec<usize>)>,
+    /// indices into `Router::services` for routes ending at this node
+    endpoints: Vec<usize>,
+}
+
+#[derive(Debug)]
+pub enum Route {
+    Found {
+        endpoint: usize,
+        params: HashMap<String, String>,
+        /// whatever the wildcard captured, empty if the route has none
+        rest: String,
+    },
+    /// the path matched but not for this method, holds what would've been accepted
+    MethodNotAllowed(Vec<Method>),
+    NotFound,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, pattern: &str, method: Method, handler: Box<dyn Handle>) -> &mut Service {
+        let index = self.services.len();
+        let mut node = &mut self.root;
+        let mut segments = pattern.split('/').filter(|s| !s.is_empty()).peekable();
+        let mut slot = None;
+
+        while let Some(segment) = segments.next() {
+            if let Some(name) = segment.strip_prefix(':') {
+                let (existing, child) = node.param.get_or_insert_with(|| (name.to_owned(), Box::default()));
+                if existing != name {
+                    println!("ROUTER: [{pattern}] names the parameter ':{name}' where another route uses ':{existing}'");
+                    exit(1);
+                }
+                node = child;
+            }
+            else if let Some(name) = segment.strip_prefix('*') {
+                if segments.peek().is_some() {
+                    println!("ROUTER: [{pattern}] has segments after the wildcard '*{name}'");
+                    exit(1);
+                }
+                let (existing, endpoints) = node.wildcard.get_or_insert_with(|| (name.to_owned(), Vec::new()));
+                if existing != name {
+                    println!("ROUTER: [{pattern}] names the wildcard '*{name}' where another route uses '*{existing}'");
+                    exit(1);
+                }
+                slot = Some(endpoints);
+                break
+            }
+            else {
+                node = node.literals.entry(segment.to_owned()).or_default();
+            }
+        }
+        let endpoints = slot.unwrap_or(&mut node.endpoints);
+
+        if let Some(&clash) = endpoints.iter().find(|&&i| self.services[i].method == method) {
+            println!("ROUTER: [{pattern}] {method:?} is already handled by [{}]", self.services[clash].pattern);
+            exit(1);
+        }
+        endpoints.push(index);
+        self.services.push(Service {
+            pattern: pattern.to_owned(),
+            method,
+            handler,
+            layers: Vec::new(),
+            panics: 0,
+            fail_after: None,
+        });
+        &mut self.services[index]
+    }
+
+    pub fn find(&self, path: &str, method: &Method) -> Route {
+        let mut params = Vec::new();
+        let mut allowed = None;
+        match self.descend(&self.root, path, method, &mut params, &mut allowed) {
+            Some((endpoint, rest)) => Route::Found {
+                endpoint,
+                params: params.into_iter().collect(),
+                rest: rest.to_owned(),
+            },
+            None => match allowed {
+                Some(methods) => Route::MethodNotAllowed(methods),
+                None => Route::NotFound,
+            },
+        }
+    }
+
+    pub fn service(&mut self, endpoint: usize) -> &mut Service {
+        &mut self.services[endpoint]
+    }
+    pub fn services(&self) -> impl Iterator<Item = &Service> {
+        self.services.iter()
+    }
+
+    /// depth first in order of specificity, so the first hit is the most specific route.
+    /// `allowed` remembers the methods of the most specific route the path matched regardless of method
+    fn descend<'p>(
+        &self,
+        node: &Node,
+        path: &'p str,
+        method: &Method,
+        params: &mut Vec<(String, String)>,
+        allowed: &mut Option<Vec<Method>>,
+    ) -> Option<(usize, &'p str)> {
+        let path = path.trim_start_matches('/');
+
+        if path.is_empty() {
+            if let Some(endpoint) = self.pick(&node.endpoints, method, allowed) {
+                return Some((endpoint, ""))
+            }
+        }
+        else {
+            let (segment, rest) = path.split_once('/').unwrap_or((path, ""));
+
+            if let Some(child) = node.literals.get(segment) {
+                if let Some(found) = self.descend(child, rest, method, params, allowed) {
+                    return Some(found)
+                }
+            }
+            if let Some((name, child)) = &node.param {
+                params.push((name.clone(), segment.to_owned()));
+                if let Some(found) = self.descend(child, rest, method, params, allowed) {
+                    return Some(found)
+                }
+                params.pop();
+            }
+        }
+
+        if let Some((name, endpoints)) = &node.wildcard {
+            if let Some(endpoint) = self.pick(endpoints, method, allowed) {
+                params.push((name.clone(), path.to_owned()));
+                return Some((endpoint, path))
+            }
+        }
+        None
+    }
+
+    fn pick(&self, endpoints: &[usize], method: &Method, allowed: &mut Option<Vec<Method>>) -> Option<usize> {
+        let with = |wanted: Method| endpoints.iter().copied().find(|&i| self.services[i].method == wanted);
+        //REF: https://www.rfc-editor.org/rfc/rfc9110#section-9.3.2
+        // an exact match wins regardless of the order routes were added in, HEAD is answered like a GET
+        let found = with(*method)
+            .or_else(|| if *method == Method::HEAD { with(Method::GET) } else { None })
+            .or_else(|| with(Method::ANY));
+
+        if found.is_none() && allowed.is_none() && !endpoints.is_empty() {
+            let mut methods: Vec<Method> = endpoints.iter().map(|&i| self.services[i].method).collect();
+            if methods.contains(&Method::GET) && !methods.contains(&Method::HEAD) {
+                methods.push(Method::HEAD);
+            }
+            *allowed = Some(methods);
+        }
+        found
+    }
+}
+
+pub(crate) fn panic_message(payload: &dyn Any) -> &str {
+    match (payload.downcast_ref::<&str>(), payload.downcast_ref::<String>()) {
+        (Some(message), _) => message,
+        (_, Some(message)) => message,
+        _ => "<not a string>",
+    }
+}
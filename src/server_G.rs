@@ -1,41 +1,173 @@
 use core::fmt;
-use std::{collections::HashMap, io::{ErrorKind, Write}, marker::PhantomData, net::SocketAddr, sync::Arc, time::{Duration, Instant}};
+use std::{collections::{HashMap, HashSet, VecDeque}, io::{self, ErrorKind, Read, Write}, marker::PhantomData, net::SocketAddr, sync::{mpsc, Arc}, time::{Duration, Instant}};
 
-use mio::{net::{TcpListener, TcpStream}, Events, Interest, Poll, Token};
+use mio::{net::{TcpListener, TcpStream}, Events, Interest, Poll, Token, Waker};
 use rustls::ServerConfig;
 
-use crate::{helpers::{throw_reader_at_writer, Parser, SendTo}, Buffer, SERVER, TLS::TLStream, StreamId};
+use crate::{helpers::{Parser, SendTo}, Buffer, SERVER, TLS::TLStream, StreamId};
 
-pub struct Server_G<M, P, T, E, H> 
+pub struct Server_G<M, P, T, E, H, S> 
 where 
-    M: SendTo + Default,
+    M: Outgoing + Default,
     P: Parser<T, E> + Default,
     E: fmt::Display,
-    H: Handshaker + Default,
+    H: Handshaker<P, S>,
+    S: Session<T, M, E>,
 {
-    pub clients: HashMap<StreamId, Client<M, P, T, E, H>>,
+    pub clients: HashMap<StreamId, Client<M, P, T, E, H, S>>,
 
-    pub config: Arc<ServerConfig>,
-    pub listener: TcpListener,
+    /// None when clients only arrive through an `Adopter`
+    pub config: Option<Arc<ServerConfig>>,
+    pub listener: Option<TcpListener>,
     pub poll: Poll,
     pub events: Events,
     pub heartbeat: Option<Duration>,
     pub last_beat: Instant,
     pub events_processed: usize,
     pub id_to_delete: StreamId,
-    pub queued_disconnects: Vec<StreamId>,
+    pub queued_disconnects: Vec<(StreamId, Option<T>)>,
+    /// how long a peer gets to answer when we close the connection
+    pub close_timeout: Duration,
+    /// the most a client may have waiting to be written, see `overflow`
+    pub queue_limit: usize,
+    pub overflow: Overflow,
+    /// handed to every new client's handshaker
+    pub handshake: H::Config,
+    /// handed to every new client's session
+    pub session: S::Config,
+    /// named groups of clients, a client leaves all of them when it goes away
+    rooms: HashMap<String, HashSet<StreamId>>,
+    /// clients that may have more messages buffered after the one last handed to the application
+    unread: Vec<StreamId>,
+    /// set up by the first call to `adopter`
+    adoptions: Option<(mpsc::Receiver<Adoption<H>>, Adopter<H>)>,
     h: PhantomData<H>,
 }
 
+const WAKER: StreamId = StreamId::MAX;
+
+/// a connection some other server started, e.g. an http server that was asked for an upgrade
+pub struct Adoption<H> {
+    pub stream: TLStream,
+    /// picks up where the other server left off, it may already have a response to send
+    pub handshaker: H,
+    /// whatever the client sent after the request that got it here
+    pub leftover: Vec<u8>,
+}
+
+/// hands connections to a `Server_G` from any thread, see `Server_G::adopter`
+pub struct Adopter<H> {
+    sender: mpsc::Sender<Adoption<H>>,
+    waker: Arc<Waker>,
+}
+
+impl<H> Clone for Adopter<H> {
+    fn clone(&self) -> Self {
+        Self { sender: self.sender.clone(), waker: self.waker.clone() }
+    }
+}
+
+impl<H> Adopter<H> {
+    /// false if the server is gone
+    pub fn adopt(&self, adoption: Adoption<H>) -> bool {
+        if self.sender.send(adoption).is_err() { return false }
+        self.waker.wake().is_ok()
+    }
+}
+
 pub enum Notification<T> {
+    /// the client is done handshaking and may be sent messages
+    Connected(StreamId),
     SentMessage(StreamId, T),
-    Disconnected(StreamId),
+    /// carries whatever the peer said on its way out, if the protocol has a way of saying it
+    Disconnected(StreamId, Option<T>),
     Heartbeat,
 }
 
-pub trait Handshaker: SendTo {
+/// a message that can wait its turn in a client's queue
+pub trait Outgoing: SendTo {
+    /// bytes not yet written
+    fn remaining(&self) -> usize;
+    /// what it counts for against `Server_G::queue_limit`, parts that aren't made yet included
+    fn footprint(&self) -> usize { self.remaining() }
+    /// goes ahead of everything queued that isn't urgent, e.g. a pong
+    fn urgent(&self) -> bool { false }
+    /// once this is written, the next part of the same message if it comes in parts.
+    /// only urgent messages are sent in between
+    fn next_part(&mut self) -> io::Result<Option<Self>> where Self: Sized { Ok(None) }
+}
+
+/// what happens when a message would take a client over `Server_G::queue_limit`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overflow {
+    /// refuse the message with `SendError::QueueFull`
+    #[default]
+    Reject,
+    /// throw away messages that haven't started going out, oldest first, until it fits. urgent ones are kept
+    DropOldest,
+    /// the client can't keep up, disconnect it
+    DropClient,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendError {
+    /// the client was dropped or is hanging up
+    Gone,
+    QueueFull,
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendError::Gone => write!(f, "the client is gone"),
+            SendError::QueueFull => write!(f, "the client's queue is full"),
+        }
+    }
+}
+
+impl std::error::Error for SendError {}
+
+/// what to do with a message the protocol has something to say about
+pub enum Verdict<T, M> {
+    /// the application gets to see it
+    Deliver(T),
+    /// answered on the application's behalf
+    Reply(M),
+    /// send the reply if there is one, then hang up and report `T` with the disconnect
+    Close(Option<M>, T),
+    Ignore,
+}
+
+/// the rules of a protocol once the handshake is done, e.g. answering a websocket close
+pub trait Session<T, M, E> {
+    /// shared by all sessions of a server, see `Server_G::session`
+    type Config: Default;
+
+    fn new(config: &Self::Config) -> Self;
+    fn receive(&mut self, message: T) -> Verdict<T, M>;
+    /// the parser gave up on what the client sent, a chance to say why before hanging up
+    fn fault(&mut self, error: E) -> Verdict<T, M>;
+    /// a chance to change how a message goes out before it's queued
+    fn prepare(&mut self, _message: &mut M) {}
+    /// called on every heartbeat once the client is done handshaking
+    fn beat(&mut self, config: &Self::Config, now: Instant) -> Verdict<T, M>;
+    /// when `expire` should be called next, if ever
+    fn deadline(&self) -> Option<Instant>;
+    fn expire(&mut self, now: Instant) -> Verdict<T, M>;
+    /// the connection went without either side saying goodbye, what to report that as
+    fn lost(&mut self) -> Option<T> { None }
+}
+
+pub trait Handshaker<P, S>: SendTo {
+    /// shared by all handshakes of a server, see `Server_G::handshake`
+    type Config: Default;
+
+    fn new(config: &Self::Config) -> Self;
     // returning None indicates a handshake error, if no progress is made just return an empty vec
+    // to refuse a client with a response, send it as `Responding` and return None once it's written
     fn handshake<'b>(&mut self, buf: &'b [u8]) -> Option<(HandshakeStatus, &'b [u8])>;
+    /// hands whatever was agreed on to the parser and the session
+    fn finish(&mut self, parser: &mut P, session: &mut S);
 }
 
 pub enum HandshakeStatus {
@@ -44,12 +176,13 @@ pub enum HandshakeStatus {
     Done,
 }
 
-impl<M, P, T, E, H> Server_G<M, P, T, E, H> 
+impl<M, P, T, E, H, S> Server_G<M, P, T, E, H, S> 
 where 
-    M: SendTo + Default + From<Vec<u8>>,
+    M: Outgoing + Default + From<Vec<u8>>,
     P: Parser<T, E> + Default,
     E: fmt::Display,
-    H: Handshaker + Default,
+    H: Handshaker<P, S>,
+    S: Session<T, M, E>,
 {
     pub fn new(address: SocketAddr, config: ServerConfig) -> Self {
         let poll = Poll::new().unwrap();
@@ -60,9 +193,16 @@ where
 
         println!("HTTPSERVER: initializing server on (https://{}:{})", address.ip(), address.port());
 
+        Self::with(Some(listener), Some(Arc::new(config)), poll)
+    }
+    /// a server without a port of its own, clients are handed to it through `adopter`
+    pub fn detached() -> Self {
+        Self::with(None, None, Poll::new().unwrap())
+    }
+    fn with(listener: Option<TcpListener>, config: Option<Arc<ServerConfig>>, poll: Poll) -> Self {
         Self { 
             clients: HashMap::with_capacity(1028),
-            config: Arc::new(config),
+            config,
             listener,
             poll,
             events: Events::with_capacity(128),
@@ -71,24 +211,66 @@ where
             events_processed: 0,
             id_to_delete: 0,
             queued_disconnects: Vec::with_capacity(64),
+            close_timeout: Duration::from_secs(5),
+            queue_limit: 16 << 20,
+            overflow: Overflow::default(),
+            handshake: H::Config::default(),
+            session: S::Config::default(),
+            rooms: HashMap::new(),
+            unread: Vec::new(),
+            adoptions: None,
             h: PhantomData,
         }
     }
+    /// for handing over connections from another server, they're registered and carry on handshaking here
+    pub fn adopter(&mut self) -> Adopter<H> {
+        let (_, adopter) = self.adoptions.get_or_insert_with(|| {
+            let waker = Waker::new(self.poll.registry(), Token(WAKER)).unwrap();
+            let (sender, receiver) = mpsc::channel();
+            (receiver, Adopter { sender, waker: Arc::new(waker) })
+        });
+        adopter.clone()
+    }
     pub fn serve(&mut self) -> Notification<T> {
         loop {
-            if let Some(id) = self.queued_disconnects.pop() {
-                return Notification::Disconnected(id)
+            if let Some((id, last_words)) = self.queued_disconnects.pop() {
+                return Notification::Disconnected(id, last_words)
+            }
+            while let Some(id) = self.unread.pop() {
+                // a read that stopped at the parser's room left the rest on the socket, with no event coming for it
+                if let Some(client) = self.clients.get_mut(&id).filter(|client| client.capped) {
+                    match client.read() {
+                        Ok(()) => {},
+                        Err(e) if e.kind() == ErrorKind::WouldBlock => {},
+                        Err(e) => {
+                            println!("TLServer: dropped client on account of error when reading: {e}");
+                            self.drop_client(id);
+                            continue
+                        }
+                    }
+                }
+                if let Some(notification) = self.read_messages(id) {
+                    return notification
+                }
+            }
+            if let Some(notification) = self.expire_sessions() {
+                return notification
             }
             let mut time = None;
             let non_blocking = self.heartbeat == Some(Duration::ZERO);
             if let Some(beat) = self.heartbeat {
                 let timer = beat.saturating_sub(self.last_beat.elapsed());
-                self.last_beat = Instant::now();
                 if timer.is_zero() && !non_blocking {
+                    self.beat_sessions();
                     return Notification::Heartbeat
                 }
                 time = Some(timer)
             }
+            let deadline = self.clients.values().filter_map(|client| client.session.deadline()).min();
+            if let Some(deadline) = deadline {
+                let timer = deadline.saturating_duration_since(Instant::now());
+                time = Some(time.map_or(timer, |time: Duration| time.min(timer)));
+            }
             if self.events.iter().nth(self.events_processed).is_none() {
                 self.events_processed = 0;
                 println!("timeout = {time:?}");
@@ -98,6 +280,7 @@ where
                     Err(e) => panic!("{e}"),
                 }
                 if non_blocking && self.events.is_empty() { 
+                    self.beat_sessions();
                     return Notification::Heartbeat
                 }
             }
@@ -105,9 +288,11 @@ where
                 // println!("{:?}", event);
                 self.events_processed += 1;
                 let id = event.token().0 as StreamId;
+                let (readable, writable) = (event.is_readable(), event.is_writable());
                 match id {
                     SERVER => {
-                        match self.listener.accept() {
+                        let Some(listener) = &self.listener else { continue };
+                        match listener.accept() {
                             Ok((client, _)) => {
                                 self.register(client);
                                 println!("registered client")
@@ -118,12 +303,13 @@ where
                             },
                         }
                     }
+                    WAKER => self.take_adoptions(),
                     _client => {
                         let client = self.clients.get_mut(&id).unwrap();
                         let stream = &mut client.stream;
 
                         // TLS layer handshaking
-                        if stream.tls.is_handshaking() {
+                        if stream.is_handshaking() {
                             match client.stream.handshake() {
                                 Ok(_) => {},
                                 Err(e) if e.kind() == ErrorKind::WouldBlock => {},
@@ -131,13 +317,14 @@ where
                                 Err(e) => {
                                     println!("TLServer: dropped client on account of error when handshaking: {e}");
                                     self.drop_client(id);
-                                    // THE APPLICATION DOESN'T NEED TO KNOW
-                                    let _ = self.queued_disconnects.pop();
+                                    continue
                                 },
                             };
-                            continue
+                            // the client's first message may have come in with the end of the handshake
+                            if client.stream.is_handshaking() { continue }
                         }
-                        if event.is_writable() {
+                        let stream = &mut client.stream;
+                        if writable {
                             //TODO: this might be redundant
                             match stream.flush() {
                                 Ok(_) => {},
@@ -153,20 +340,13 @@ where
                                 },
                             }
                             if !client.is_handshaking {
-                                match client.messenger.send_all(stream) {
-                                    Ok(_) => {},
-                                    Err(e) if e.kind() == ErrorKind::WouldBlock => {},
-                                    Err(e) => {
-                                        println!("HTTP_SERVER: dropped client on account of error when writing: {e}");
-                                        self.drop_client(id);
-                                        continue
-                                    }
-                                };
+                                self.flush_client(id);
                             }
-
                         }
-                        if event.is_readable() {
-                            match throw_reader_at_writer(stream, &mut client.buf) {
+                        // flushing may have dropped it
+                        let Some(client) = self.clients.get_mut(&id) else { continue };
+                        if readable {
+                            match client.read() {
                                 Ok(()) => {},
                                 Err(e) if e.kind() == ErrorKind::WouldBlock => {},
                                 Err(e) => {
@@ -177,39 +357,15 @@ where
                             };
                             if !client.is_handshaking {
                                 if !client.buf.has_read() { continue }
-                                let story = client.buf.the_story_so_far();
-    
-                                match client.parser.parse(story) {
-                                    Ok((Some(message), rest)) => {
-                                        client.buf.consume(client.buf.data.len() - rest.len());
-                                        client.sent_message = true;
-    
-                                        return Notification::SentMessage(id, message)
-                                    },
-                                    Ok((None, rest)) => {
-                                        if rest == client.buf.data {
-                                            println!("TLServer: request incomplete message at size {}", story.len())
-                                        }
-                                        else {
-                                            let old_len = client.buf.data.len();
-                                            let new_len = rest.len();
-                                            client.buf.data.copy_within(old_len-new_len.., 0);
-                                            client.buf.data.resize(new_len, 0);
-                                            client.buf.read = new_len;
-                                            client.buf.prev_read = new_len;
-                                            println!("TlServer: read {} bytes for handshake", old_len - new_len)
-                                        }
-                                    },
-                                    Err(e) => {
-                                        println!("TLServer: dropped client on account of error when parsing request: {e}");
-                                        self.drop_client(id);
-                                        continue
-                                    },
+                                if let Some(notification) = self.read_messages(id) {
+                                    return notification
                                 }
+                                continue
                             }
 
                         }
                         if !client.is_handshaking { continue }
+                        let stream = &mut client.stream;
 
                         // Application layer handshaking
                         loop {
@@ -230,7 +386,11 @@ where
                                         },
                                         HandshakeStatus::Done => {
                                             println!("HTTP_SERVER: finished handshake with client {id}");
-                                            client.is_handshaking = false
+                                            client.handshaker.finish(&mut client.parser, &mut client.session);
+                                            client.is_handshaking = false;
+                                            // messages may have come in right behind the handshake
+                                            self.unread.push(id);
+                                            return Notification::Connected(id)
                                         },
                                     }
                                 },
@@ -245,53 +405,272 @@ where
             }
         }
     }
+    /// drops the client without a goodbye, reported with whatever its session makes of it.
+    /// a client that never finished the handshake was never announced, so it goes unreported
     pub fn drop_client(&mut self, id: StreamId) {
-        let client = self.clients.get_mut(&id).unwrap();
-        
-        let registry = self.poll.registry();
-        registry.deregister(&mut client.stream).unwrap();
-        
-        if client.sent_message {
-            self.queued_disconnects.push(id);
+        let mut client = self.clients.remove(&id).unwrap();
+        self.poll.registry().deregister(&mut client.stream).unwrap();
+        self.leave_all(id);
+        if !client.is_handshaking {
+            // if it was being hung up on, that's still what it was about
+            let last_words = client.hanging_up.take().or_else(|| client.session.lost());
+            self.queued_disconnects.push((id, last_words));
         }
-        self.clients.remove(&id).unwrap();
     }
-    pub fn send_to_client(&mut self, id: StreamId, message: impl Into<M>) {
-        let client = self.clients.get_mut(&id).unwrap();
-        client.messenger = message.into();
-        match client.messenger.send_all(&mut client.stream) {
-            Ok(_) => {},
-            Err(e) if e.kind() == ErrorKind::WouldBlock => {},
-            Err(e) => {
-                println!("HTTP_SERVER: dropped client on account of error when writing: {e}");
-                self.drop_client(id);
+    /// drops the client, reporting `last_words` regardless of whether it ever sent a message
+    pub fn disconnect(&mut self, id: StreamId, last_words: T) {
+        let mut client = self.clients.remove(&id).unwrap();
+        self.poll.registry().deregister(&mut client.stream).unwrap();
+        self.leave_all(id);
+        self.queued_disconnects.push((id, Some(last_words)));
+    }
+    /// false if there's no such client
+    pub fn join(&mut self, id: StreamId, room: &str) -> bool {
+        if !self.clients.contains_key(&id) { return false }
+        self.rooms.entry(room.to_owned()).or_default().insert(id);
+        true
+    }
+    pub fn leave(&mut self, id: StreamId, room: &str) {
+        if let Some(members) = self.rooms.get_mut(room) {
+            members.remove(&id);
+            if members.is_empty() {
+                self.rooms.remove(room);
             }
-        };
+        }
+    }
+    pub fn members(&self, room: &str) -> impl Iterator<Item = StreamId> + '_ {
+        self.rooms.get(room).into_iter().flatten().copied()
+    }
+    pub fn rooms_of(&self, id: StreamId) -> impl Iterator<Item = &str> {
+        self.rooms.iter()
+            .filter(move |(_, members)| members.contains(&id))
+            .map(|(room, _)| room.as_str())
+    }
+    fn leave_all(&mut self, id: StreamId) {
+        self.rooms.retain(|_, members| {
+            members.remove(&id);
+            !members.is_empty()
+        });
+    }
+    /// parses what the client has sent so far, up to the first message for the application
+    fn read_messages(&mut self, id: StreamId) -> Option<Notification<T>> {
+        loop {
+            let client = self.clients.get_mut(&id)?;
+            if client.hanging_up.is_some() || client.buf.data.is_empty() { return None }
+            let story = client.buf.the_story_so_far();
+
+            match client.parser.parse(story) {
+                Ok((Some(message), rest)) => {
+                    client.buf.consume(client.buf.data.len() - rest.len());
+                    client.sent_message = true;
+
+                    let verdict = client.session.receive(message);
+                    if let Some(notification) = self.judge(id, verdict) {
+                        self.unread.push(id);
+                        return Some(notification)
+                    }
+                },
+                Ok((None, rest)) => {
+                    // the parser may have kept the frames of a message that isn't all in yet
+                    let consumed = story.len() - rest.len();
+                    if consumed == 0 {
+                        println!("TLServer: request incomplete message at size {}", story.len())
+                    }
+                    else {
+                        client.buf.consume(consumed);
+                        println!("TLServer: stashed {consumed} bytes of an unfinished message")
+                    }
+                    if client.capped { self.unread.push(id) }
+                    return None
+                },
+                Err(e) => {
+                    println!("TLServer: hanging up on client {id} on account of error when parsing: {e}");
+                    let verdict = client.session.fault(e);
+                    return self.judge(id, verdict)
+                },
+            }
+        }
+    }
+    /// carries out what the session decided, returning the notification for the application if there is one
+    fn judge(&mut self, id: StreamId, verdict: Verdict<T, M>) -> Option<Notification<T>> {
+        match verdict {
+            Verdict::Deliver(message) => return Some(Notification::SentMessage(id, message)),
+            Verdict::Reply(reply) => {
+                if let Err(e) = self.send_to_client(id, reply) {
+                    println!("TLServer: couldn't reply to client {id}: {e}");
+                }
+            },
+            Verdict::Close(Some(reply), last_words) => {
+                // whatever is queued goes out first
+                if let Err(e) = self.send_to_client(id, reply) {
+                    println!("TLServer: couldn't reply to client {id}: {e}");
+                }
+                if let Some(client) = self.clients.get_mut(&id) {
+                    client.hanging_up = Some(last_words);
+                    self.flush_client(id);
+                }
+            },
+            Verdict::Close(None, last_words) => self.disconnect(id, last_words),
+            Verdict::Ignore => {},
+        }
+        None
+    }
+    /// whatever the sessions have to say is sent or queued, a beat has nothing to deliver
+    fn beat_sessions(&mut self) {
+        let now = Instant::now();
+        self.last_beat = now;
+        let ids: Vec<StreamId> = self.clients.iter()
+            .filter(|(_, client)| !client.is_handshaking && client.hanging_up.is_none())
+            .map(|(id, _)| *id)
+            .collect();
+        for id in ids {
+            let client = self.clients.get_mut(&id).unwrap();
+            let verdict = client.session.beat(&self.session, now);
+            let _ = self.judge(id, verdict);
+        }
+    }
+    fn expire_sessions(&mut self) -> Option<Notification<T>> {
+        let now = Instant::now();
+        let expired: Vec<StreamId> = self.clients.iter()
+            .filter(|(_, client)| client.session.deadline().is_some_and(|deadline| deadline <= now))
+            .map(|(id, _)| *id)
+            .collect();
+        for id in expired {
+            // whatever's left over is picked up on the next call
+            let verdict = self.clients.get_mut(&id).unwrap().session.expire(now);
+            if let Some(notification) = self.judge(id, verdict) {
+                return Some(notification)
+            }
+            if let Some((id, last_words)) = self.queued_disconnects.pop() {
+                return Some(Notification::Disconnected(id, last_words))
+            }
+        }
+        None
+    }
+    /// queues the message behind whatever the client hasn't been sent yet
+    pub fn send_to_client(&mut self, id: StreamId, message: impl Into<M>) -> Result<(), SendError> {
+        let client = self.clients.get_mut(&id).ok_or(SendError::Gone)?;
+        if client.hanging_up.is_some() { return Err(SendError::Gone) }
+
+        let mut message = message.into();
+        client.session.prepare(&mut message);
+        let size = message.footprint();
+        if client.queued_bytes() + size > self.queue_limit {
+            match self.overflow {
+                Overflow::Reject => return Err(SendError::QueueFull),
+                Overflow::DropOldest => {
+                    // the one being written can't be taken back, so a huge message may still go over
+                    while client.queued_bytes() + size > self.queue_limit {
+                        // urgent ones like pongs are owed to the peer, they're never dropped
+                        let Some(oldest) = client.outbox.iter().position(|queued| !queued.urgent()) else { break };
+                        let oldest = client.outbox.remove(oldest).unwrap();
+                        client.outbox_bytes -= oldest.footprint();
+                    }
+                },
+                Overflow::DropClient => {
+                    println!("TLServer: dropped client {id} with {} bytes queued", client.queued_bytes());
+                    self.drop_client(id);
+                    return Err(SendError::Gone)
+                },
+            }
+        }
+        client.outbox_bytes += size;
+        if message.urgent() {
+            let behind = client.outbox.iter().take_while(|queued| queued.urgent()).count();
+            client.outbox.insert(behind, message);
+        }
+        else {
+            client.outbox.push_back(message);
+        }
+        self.flush_client(id);
+        Ok(())
+    }
+    /// bytes waiting to be written to the client
+    pub fn queued_bytes(&self, id: StreamId) -> Option<usize> {
+        self.clients.get(&id).map(Client::queued_bytes)
+    }
+    /// writes as much of the client's queue as it takes, then drops it if it's hanging up and everything went out
+    fn flush_client(&mut self, id: StreamId) {
+        let Some(client) = self.clients.get_mut(&id) else { return };
+        loop {
+            // a write can come up short without blocking when the tls buffer is full
+            while client.messenger.remaining() > 0 {
+                match client.messenger.send_all(&mut client.stream) {
+                    Ok(_) => {},
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => return,
+                    Err(e) => {
+                        println!("TLServer: dropped client on account of error when writing: {e}");
+                        self.drop_client(id);
+                        return
+                    }
+                }
+            }
+            match client.messenger.next_part() {
+                Ok(part) => client.resume = client.resume.take().or(part),
+                Err(e) => {
+                    println!("TLServer: dropped client on account of error when producing a message: {e}");
+                    self.drop_client(id);
+                    return
+                }
+            }
+            let urgent = client.outbox.front().is_some_and(M::urgent);
+            client.messenger = match client.resume.take() {
+                Some(part) if !urgent => part,
+                resume => {
+                    client.resume = resume;
+                    let Some(next) = client.outbox.pop_front() else { break };
+                    client.outbox_bytes -= next.footprint();
+                    next
+                }
+            };
+        }
+        if let Some(last_words) = client.hanging_up.take() {
+            self.disconnect(id, last_words);
+        }
     }
     fn register(&mut self, client: TcpStream) -> StreamId {
+        let stream = match &self.config {
+            Some(config) => TLStream::new(client, config.clone()),
+            None => TLStream::plain(client),
+        };
+        let handshaker = H::new(&self.handshake);
+        self.insert(stream, handshaker)
+    }
+    fn insert(&mut self, mut stream: TLStream, handshaker: H) -> StreamId {
         let registry = self.poll.registry();
-        let mut stream = TLStream::new(client, self.config.clone());
         let mut id = fastrand::usize(..);
-        while self.clients.contains_key(&id) {
+        while self.clients.contains_key(&id) || id == WAKER {
             id = fastrand::usize(..);
         }
         let token = Token(id as usize);
         let interests = Interest::READABLE | Interest::WRITABLE;
         registry.register(&mut stream, token, interests).unwrap();
 
-        let client = Client::new(id, stream);
+        let client = Client::new(id, stream, handshaker, S::new(&self.session));
         self.clients.insert(id, client);
 
         return id
     }
+    /// registers whatever the adopters sent, the handshake carries on once the stream is writable
+    fn take_adoptions(&mut self) {
+        let Some((receiver, _)) = &self.adoptions else { return };
+        let adoptions: Vec<Adoption<H>> = receiver.try_iter().collect();
+        for Adoption { stream, handshaker, leftover } in adoptions {
+            let id = self.insert(stream, handshaker);
+            let client = self.clients.get_mut(&id).unwrap();
+            let _ = client.buf.write_all(&leftover);
+            println!("TLServer: adopted client {id}");
+        }
+    }
 }
 
-impl<M, P, T, E, H> Iterator for &mut Server_G<M, P, T, E, H>
+impl<M, P, T, E, H, S> Iterator for &mut Server_G<M, P, T, E, H, S>
 where 
-    M: SendTo + Default + From<Vec<u8>>,
+    M: Outgoing + Default + From<Vec<u8>>,
     P: Parser<T, E> + Default,
     E: fmt::Display,
-    H: Handshaker + Default,
+    H: Handshaker<P, S>,
+    S: Session<T, M, E>,
 {
     type Item = Notification<T>;
     fn next(&mut self) -> Option<Self::Item> {
@@ -299,42 +678,80 @@ where
     }
 }
 
-pub struct Client<M, P, T, E, H>
+pub struct Client<M, P, T, E, H, S>
 where 
-    M: SendTo + Default,
+    M: Outgoing + Default,
     P: Parser<T, E> + Default,
     E: fmt::Display,
-    H: Handshaker + Default,
+    H: Handshaker<P, S>,
+    S: Session<T, M, E>,
 {
     pub id: StreamId,
     pub stream: TLStream,
     pub buf: Buffer,
+    /// the message being written, the rest wait in `outbox`
     pub messenger: M,
+    /// the rest of a message that goes out in parts, it waits only for urgent messages
+    pub resume: Option<M>,
+    pub outbox: VecDeque<M>,
+    outbox_bytes: usize,
     pub parser: P,
     pub handshaker: H,
+    pub session: S,
     pub is_handshaking: bool,
     pub sent_message: bool,
+    /// set once we're only waiting on the queue to empty before dropping the client
+    pub hanging_up: Option<T>,
+    /// the last read stopped at the parser's room, so there may be more waiting on the socket
+    capped: bool,
     t: PhantomData<T>,
     e: PhantomData<E>,
 }
 
-impl<M, P, T, E, H> Client<M, P, T, E, H>
+impl<M, P, T, E, H, S> Client<M, P, T, E, H, S>
 where 
-    M: SendTo + Default,
+    M: Outgoing + Default,
     P: Parser<T, E> + Default,
     E: fmt::Display,
-    H: Handshaker + Default,
+    H: Handshaker<P, S>,
+    S: Session<T, M, E>,
 {
-    fn new(id: StreamId, stream: TLStream) -> Self {
+    pub fn queued_bytes(&self) -> usize {
+        let resume = self.resume.as_ref().map_or(0, M::footprint);
+        self.messenger.footprint() + resume + self.outbox_bytes
+    }
+    /// reads what's waiting, but no more than the parser has room for. A client being hung up on
+    /// has had its say, what it sends now is thrown away
+    fn read(&mut self) -> io::Result<()> {
+        if self.hanging_up.is_some() {
+            io::copy(&mut self.stream, &mut io::sink())?;
+            return Ok(())
+        }
+        let room = match self.is_handshaking {
+            true => u64::MAX,
+            false => self.parser.room().saturating_sub(self.buf.data.len()) as u64,
+        };
+        let mut capped = (&mut self.stream).take(room);
+        let read = io::copy(&mut capped, &mut self.buf);
+        self.capped = capped.limit() == 0;
+        read.map(|_| ())
+    }
+    fn new(id: StreamId, stream: TLStream, handshaker: H, session: S) -> Self {
         Self {
             id,
             stream,
             buf: Buffer::with_capacity(4096), //TODO: maybe this should be less aligned?
             messenger: M::default(),
+            resume: None,
+            outbox: VecDeque::new(),
+            outbox_bytes: 0,
             parser: P::default(),
-            handshaker: H::default(),
+            handshaker,
+            session,
             is_handshaking: true,
             sent_message: false,
+            hanging_up: None,
+            capped: false,
             t: PhantomData,
             e: PhantomData,
         }
@@ -5,11 +5,11 @@ use std::error::Error;
 
 use camino::Utf8PathBuf;
 
-use crate::helpers::Parser;
+use crate::helpers::{percent_decode, Parser};
 use crate::http::*;
 
 pub trait HttpSmith {
-    fn serialize(&self, response: &Response) -> (Vec<u8>, Utf8PathBuf);
+    fn serialize(&self, response: &Response) -> Vec<u8>;
     fn deserialize<'b>(&self, buf: &'b [u8]) -> Result<(Request, &'b [u8]), ParseError>;
 }
 
@@ -17,7 +17,7 @@ pub trait HttpSmith {
 pub struct HttpSmithText;
 
 impl HttpSmith for HttpSmithText {
-    fn serialize(&self, response: &Response) -> (Vec<u8>, Utf8PathBuf) {
+    fn serialize(&self, response: &Response) -> Vec<u8> {
         let mut data = Vec::with_capacity(256);
 
         data.append(&mut response.version.to_str().to_owned().into_bytes());
@@ -34,12 +34,22 @@ impl HttpSmith for HttpSmithText {
         }
         data.append(&mut "\r\n".to_owned().into_bytes());
 
-        (data, response.body.clone())
+        data
     }
     fn deserialize<'b>(&self, buf: &'b [u8]) -> Result<(Request, &'b [u8]), ParseError> {
+        let (mut request, content_length, rest) = self.deserialize_head(buf)?;
+        let (body, rest) = rest.split_at_checked(content_length).ok_or(ParseError::Incomplete)?;
+        request.body = body.to_owned();
+        return Ok((request, rest));
+    }
+}
+
+impl HttpSmithText {
+    /// the request without its body, the length the body has and what follows the head
+    pub fn deserialize_head<'b>(&self, buf: &'b [u8]) -> Result<(Request, usize, &'b [u8]), ParseError> {
         use ParseError::*;
         
-        let (header, mut rest) = header_from_bytes(buf)?;
+        let (header, rest) = header_from_bytes(buf)?;
         //println!("{header}");
         let lines: Vec<&str> = header.split("\r\n").collect();
         let (request_line, headers) = lines.split_at(1);
@@ -49,10 +59,9 @@ impl HttpSmith for HttpSmithText {
         if request_line.len() != 3 { return Err(BadStatusLine); }
 
         let method = Method::parse(request_line[0]).ok_or(BadMethod)?;
-        let (path, query_params) = match request_line[1].split_once('?') {
-            Some((path, query)) => (path.into(), parse_query_parameters(query)?),
-            None => (request_line[1].into(), HashMap::new()),
-        };
+        let (path, query) = request_line[1].split_once('?').unwrap_or((request_line[1], ""));
+        let path = parse_target(path)?;
+        let query_params = parse_query_parameters(query)?;
 
         //REF: https://www.rfc-editor.org/rfc/rfc9112.html#section-2.2-8
         if request_line[2].ends_with(|c: char| c.is_whitespace()) { return  Err(WhiteSpaceAfterStartLine) }
@@ -64,9 +73,18 @@ impl HttpSmith for HttpSmithText {
             path,
             version,
             headers: HashMap::new(),
+            query: query.to_owned(),
             query_params,
+            params: HashMap::new(),
             body: Vec::new(),
+            peer: None,
+            secure: false,
+            id: 0,
+            form_limits: Default::default(),
+            state: Default::default(),
+            multipart: None,
         };
+        let mut content_length = 0;
         for header in headers {
             let (key, value) = header.split_once(":").ok_or(MissingValueInField)?;
             let key = key.to_ascii_lowercase();
@@ -78,14 +96,11 @@ impl HttpSmith for HttpSmithText {
             if key.starts_with(|c: char| c.is_whitespace()) { return Err(DeprecatedHeaderFolding) }
             
             if key == "content-length" {
-                let content_length = value.parse::<usize>().map_err(|_| InvalidContentLength )?;
-                let body;
-                (body, rest) = rest.split_at_checked(content_length).ok_or(Incomplete)?;
-                request.body = body.to_owned()
+                content_length = value.parse::<usize>().map_err(|_| InvalidContentLength )?;
             }
             request.headers.insert(key, value);
         }
-        return Ok((request, rest));
+        return Ok((request, content_length, rest));
     }
 }
 
@@ -99,16 +114,67 @@ impl Parser<Request, ParseError> for HttpSmithText {
     }
 }
 
-fn parse_query_parameters(query: &str) -> Result<HashMap<String, String>, ParseError> {
+//REF: https://www.rfc-editor.org/rfc/rfc3986#section-3.3
+fn parse_target(target: &str) -> Result<Utf8PathBuf, ParseError> {
     use ParseError::*;
 
-    let mut map = HashMap::new();
-    for param in query.split('&') {
-        let (key, value) = param.split_once('=').ok_or(BadQuery)?;
-        println!("QUERY: key = {key}, value = {value}");
-        map.insert(key.to_owned(), value.to_owned());
+    if !target.starts_with('/') { return Err(BadTarget) }
+
+    let mut segments = Vec::new();
+    for segment in target.split('/').skip(1) {
+        let decoded = percent_decode(segment.as_bytes()).ok_or(BadTarget)?;
+        // an encoded slash would sneak a separator past the router and path_is_sane, a NUL past the OS
+        if decoded.contains(&b'/') || decoded.contains(&0) { return Err(BadTarget) }
+        segments.push(String::from_utf8(decoded).map_err(|_| BadTarget)?);
+    }
+    Ok(remove_dot_segments(&segments).into())
+}
+
+//REF: https://www.rfc-editor.org/rfc/rfc3986#section-5.2.4
+/// `..` can't climb above the root, a trailing dot segment keeps the trailing slash
+pub(crate) fn remove_dot_segments(segments: &[impl AsRef<str>]) -> String {
+    let mut output: Vec<&str> = Vec::with_capacity(segments.len());
+    let mut trailing_slash = false;
+    for segment in segments {
+        trailing_slash = false;
+        match segment.as_ref() {
+            "." => trailing_slash = true,
+            ".." => {
+                output.pop();
+                trailing_slash = true;
+            }
+            segment => output.push(segment),
+        }
     }
-    return Ok(map)
+    let mut path = String::with_capacity(segments.iter().map(|s| s.as_ref().len() + 1).sum());
+    for segment in &output {
+        path.push('/');
+        path.push_str(segment);
+    }
+    if path.is_empty() || trailing_slash && !path.ends_with('/') {
+        path.push('/');
+    }
+    path
+}
+
+//REF: https://url.spec.whatwg.org/#urlencoded-parsing
+fn parse_query_parameters(query: &str) -> Result<QueryParams, ParseError> {
+    use ParseError::*;
+
+    let decode = |s: &str| {
+        let bytes = s.replace('+', " ");
+        percent_decode(bytes.as_bytes()).and_then(|bytes| String::from_utf8(bytes).ok()).ok_or(BadQuery)
+    };
+
+    let mut params = QueryParams::default();
+    for param in query.split('&').filter(|param| !param.is_empty()) {
+        let (key, value) = match param.split_once('=') {
+            Some((key, value)) => (decode(key)?, Some(decode(value)?)),
+            None => (decode(param)?, None),
+        };
+        params.0.push((key, value));
+    }
+    return Ok(params)
 }
 
 //REF: https://www.rfc-editor.org/rfc/rfc9112.html#section-2.2-2
@@ -148,6 +214,7 @@ pub enum ParseError {
     BadStatusLine,
     BadMethod,
     UnknownVersion,
+    BadTarget,
     BadQuery,
     MissingValueInField,
     WhitespaceBeforeColon,
@@ -162,4 +229,38 @@ impl std::fmt::Display for ParseError {
     }
 }
 
-impl Error for ParseError {}
\ No newline at end of file
+impl Error for ParseError {}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(raw: &str) -> Result<String, ParseError> {
+        parse_target(raw).map(|path| path.into_string())
+    }
+
+    #[test]
+    fn encoded_dot_segments_cant_climb() {
+        assert_eq!(target("/a/%2e%2e/%2E%2E/etc/passwd").as_deref(), Ok("/etc/passwd"));
+        assert_eq!(target("/..").as_deref(), Ok("/"));
+        assert_eq!(target("/../../a").as_deref(), Ok("/a"));
+        assert_eq!(target("/a/b/..").as_deref(), Ok("/a/"));
+        assert_eq!(target("/a/./b/.").as_deref(), Ok("/a/b/"));
+        assert_eq!(target("/a/%2e/b").as_deref(), Ok("/a/b"));
+    }
+
+    #[test]
+    fn decodes_segments() {
+        assert_eq!(target("/caf%C3%A9/a%20b").as_deref(), Ok("/café/a b"));
+        assert_eq!(target("/").as_deref(), Ok("/"));
+    }
+
+    #[test]
+    fn refuses_what_decoding_would_sneak_in() {
+        assert_eq!(target("/a%2Fb/c"), Err(ParseError::BadTarget));
+        assert_eq!(target("/a%2f..%2f..%2fetc"), Err(ParseError::BadTarget));
+        assert_eq!(target("/a%00.html"), Err(ParseError::BadTarget));
+        assert_eq!(target("/%ff"), Err(ParseError::BadTarget));
+        assert_eq!(target("/%2"), Err(ParseError::BadTarget));
+        assert_eq!(target("a/b"), Err(ParseError::BadTarget));
+    }
+}
@@ -0,0 +1,76 @@
+//! Application state shared between services.
+//!
+//! Everything runs on the one thread driving the poll loop and a handler always runs to completion,
+//! so state lives in an `Rc<RefCell<T>>` rather than behind a lock. A handler can't be interrupted
+//! while it holds a borrow, the only way to hit a `BorrowMutError` is to hold one yourself
+//! (across a call to `serve` or while borrowing the same state again further down).
+//! ```ignore
+//! let hits = server.http.add_state(Hits(0));
+//! server.http.add_service("/hit", Method::POST, |State(hits): State<Hits>| {
+//!     hits.borrow_mut().0 += 1;
+//!     Status::NoContent
+//! });
+//! ```
+
+use std::any::{type_name, Any, TypeId};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Deref;
+use std::rc::Rc;
+
+use crate::extract::{rejection, FromRequest};
+use crate::http::{Request, Response, Status};
+
+/// one value per type, cheap to clone since everything in it is reference counted
+#[derive(Clone, Default)]
+pub struct AppState {
+    values: HashMap<TypeId, Rc<dyn Any>>,
+}
+
+impl AppState {
+    /// replaces any state of the same type, returning a handle to the new one
+    pub fn insert<T: 'static>(&mut self, value: T) -> Rc<RefCell<T>> {
+        let shared = Rc::new(RefCell::new(value));
+        self.insert_shared(shared.clone());
+        shared
+    }
+    /// for state that is also used outside of the http server
+    pub fn insert_shared<T: 'static>(&mut self, shared: Rc<RefCell<T>>) {
+        self.values.insert(TypeId::of::<T>(), shared);
+    }
+    pub fn get<T: 'static>(&self) -> Option<Rc<RefCell<T>>> {
+        self.values
+            .get(&TypeId::of::<T>())
+            .cloned()
+            .and_then(|value| value.downcast().ok())
+    }
+}
+
+impl fmt::Debug for AppState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "AppState({} values)", self.values.len())
+    }
+}
+
+/// borrows state added with `HttpServer::add_state`, 500 if there is none of that type
+pub struct State<T>(pub Rc<RefCell<T>>);
+
+impl<T> Deref for State<T> {
+    type Target = RefCell<T>;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T: 'static> FromRequest for State<T> {
+    fn from_request(request: &mut Request) -> Result<Self, Response> {
+        match request.state.get::<T>() {
+            Some(shared) => Ok(State(shared)),
+            None => {
+                println!("HTTP_SERVER: a handler asked for state of type {} but none was added", type_name::<T>());
+                Err(rejection(Status::InternalServerError, "missing application state"))
+            }
+        }
+    }
+}
@@ -0,0 +1,510 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, read_dir};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom};
+use std::rc::Rc;
+use std::time::{Duration, SystemTime};
+
+use camino::{Utf8Path, Utf8PathBuf};
+
+use crate::{Body, V_file, V_listing, V_stream, VfsStats};
+
+pub trait VfsBackend {
+    fn get(&mut self, path: &Utf8Path) -> Option<Body>;
+    fn stat(&self, path: &Utf8Path) -> Option<V_stat>;
+    /// the direct children of a directory, in no particular order
+    fn list(&self, dir: &Utf8Path) -> Vec<V_listing>;
+
+    /// called once by the server before it starts serving
+    fn init(&mut self) {}
+    /// hot reloading, only meaningful for backends that mirror something mutable
+    fn sync(&mut self, _path: &Utf8Path) {}
+    fn remove(&mut self, _path: &Utf8Path) {}
+    fn stats(&self) -> VfsStats {
+        VfsStats::default()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct V_stat {
+    pub size: usize,
+    pub modified: SystemTime,
+    pub is_dir: bool,
+    /// backends that know better than the file extension can say so
+    pub mime: Option<&'static str>,
+    pub etag: Option<&'static str>,
+    /// a precompressed variant of the file
+    pub gzip: Option<&'static [u8]>,
+}
+
+impl V_stat {
+    pub fn file(size: usize, modified: SystemTime) -> Self {
+        Self { size, modified, is_dir: false, mime: None, etag: None, gzip: None }
+    }
+    pub fn dir() -> Self {
+        Self { is_dir: true, ..Self::file(0, SystemTime::UNIX_EPOCH) }
+    }
+}
+
+/// the children of `dir` among a flat set of file paths, including the implied subdirectories
+pub(crate) fn list_children<'a>(dir: &Utf8Path, files: impl Iterator<Item = (&'a Utf8PathBuf, V_stat)>, dirs: &HashSet<Utf8PathBuf>) -> Vec<V_listing> {
+    let files = files
+        .filter(|(path, _)| path.parent() == Some(dir))
+        .map(|(path, stat)| V_listing::new(path, stat));
+    let dirs = dirs.iter()
+        .filter(|path| path.parent() == Some(dir))
+        .map(|path| V_listing::new(path, V_stat::dir()));
+    files.chain(dirs).collect()
+}
+
+fn insert_ancestors(dirs: &mut HashSet<Utf8PathBuf>, path: &Utf8Path) {
+    for dir in path.ancestors().skip(1) {
+        dirs.insert(dir.into());
+    }
+}
+
+/// a directory on disk, files are cached in memory up to a budget and large files are streamed
+pub struct Directory {
+    root: Utf8PathBuf,
+    files: HashMap<Utf8PathBuf, V_entry>,
+    dirs: HashSet<Utf8PathBuf>,
+    lru: BTreeMap<u64, Utf8PathBuf>,
+    pub budget: usize,
+    pub stream_threshold: usize,
+    stats: VfsStats,
+    clock: u64,
+}
+
+impl Directory {
+    pub fn new(root: impl Into<Utf8PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            files: HashMap::new(),
+            dirs: HashSet::new(),
+            lru: BTreeMap::new(),
+            budget: 64 << 20,
+            stream_threshold: 4 << 20,
+            stats: VfsStats::default(),
+            clock: 0,
+        }
+    }
+
+    pub fn root(&self) -> &Utf8Path {
+        &self.root
+    }
+
+    fn uncache(&mut self, path: &Utf8Path) {
+        if let Some(entry) = self.files.get_mut(path) {
+            if entry.file.take().is_some() {
+                self.lru.remove(&entry.last_used);
+                self.stats.cached -= entry.size;
+            }
+        }
+    }
+
+    fn make_room(&mut self, size: usize) {
+        while self.stats.cached + size > self.budget {
+            let Some((_, coldest)) = self.lru.pop_first() else { break };
+            let entry = self.files.get_mut(&coldest).unwrap();
+            entry.file = None;
+            self.stats.cached -= entry.size;
+            self.stats.evictions += 1;
+            println!("FILE_SYSTEM: evicted {coldest} from the cache");
+        }
+    }
+
+    fn build_index(&mut self, dir: impl AsRef<Utf8Path>) {
+        let d = dir.as_ref();
+        self.dirs.insert(d.into());
+        let iter = match read_dir(self.root.join(d)) {
+            Ok(iter) => iter,
+            Err(e) => {
+                println!("FILE_SYSTEM: failed to read directory {} because of Error: {e}", self.root.join(d));
+                return
+            }
+        };
+        for f in iter.flatten() {
+            let f_ = f.file_name();
+            let Some(name) = f_.to_str() else { continue };
+
+            // we ignore dotfiles
+            if name.starts_with('.') {
+                continue
+            }
+            let path = d.join(name);
+            if f.file_type().is_ok_and(|t| t.is_dir()) {
+                self.build_index(&path)
+            }
+            else {
+                self.sync(&path)
+            }
+        }
+    }
+}
+
+impl VfsBackend for Directory {
+    fn get(&mut self, path: &Utf8Path) -> Option<Body> {
+        let size = self.files.get(path)?.size;
+        let sys_path = self.root.join(path);
+        if size > self.stream_threshold {
+            // the content-length was given from the index, a file that grew since is cut off there
+            return match V_stream::region(&sys_path, 0, size as u64) {
+                Ok(stream) => {
+                    self.stats.streamed += 1;
+                    Some(Body::Streamed(stream))
+                }
+                Err(e) => {
+                    println!("FILE_SYSTEM: failed to open file at {sys_path} for streaming because of Error: {e}");
+                    None
+                }
+            }
+        }
+        self.clock += 1;
+        let entry = self.files.get_mut(path)?;
+        if let Some(file) = &entry.file {
+            self.stats.hits += 1;
+            self.lru.remove(&entry.last_used);
+            self.lru.insert(self.clock, path.into());
+            entry.last_used = self.clock;
+            return Some(Body::Cached(file.clone()))
+        }
+        self.stats.misses += 1;
+        let file: Rc<V_file> = match fs::read(&sys_path) {
+            Ok(data) => V_file { data }.into(),
+            Err(e) => {
+                println!("FILE_SYSTEM: failed to read file at {sys_path} because of Error: {e}");
+                return None
+            }
+        };
+        // the content-length was given from the index, sending anything else would break the response
+        if file.data.len() != size {
+            println!("FILE_SYSTEM: {sys_path} changed size since it was indexed, re-indexing it");
+            self.sync(path);
+            return None
+        }
+        // files that could never fit are served once and forgotten
+        if size <= self.budget {
+            self.make_room(size);
+            let entry = self.files.get_mut(path)?;
+            entry.file = Some(file.clone());
+            entry.last_used = self.clock;
+            self.lru.insert(self.clock, path.into());
+            self.stats.cached += size;
+        }
+        Some(Body::Cached(file))
+    }
+
+    fn stat(&self, path: &Utf8Path) -> Option<V_stat> {
+        if self.dirs.contains(path) {
+            let modified = fs::metadata(self.root.join(path)).and_then(|md| md.modified()).unwrap_or(SystemTime::UNIX_EPOCH);
+            return Some(V_stat { modified, ..V_stat::dir() })
+        }
+        self.files.get(path).map(|entry| V_stat::file(entry.size, entry.modified))
+    }
+
+    fn list(&self, dir: &Utf8Path) -> Vec<V_listing> {
+        let files = self.files.iter().map(|(path, entry)| (path, V_stat::file(entry.size, entry.modified)));
+        let mut listing = list_children(dir, files, &self.dirs);
+        for entry in listing.iter_mut().filter(|entry| entry.is_dir) {
+            entry.modified = fs::metadata(self.root.join(dir).join(&entry.name)).and_then(|md| md.modified()).unwrap_or(SystemTime::UNIX_EPOCH);
+        }
+        listing
+    }
+
+    fn init(&mut self) {
+        self.build_index("");
+    }
+
+    fn sync(&mut self, path: &Utf8Path) {
+        let sys_path = self.root.join(path);
+        match fs::metadata(&sys_path) {
+            Ok(md) => {
+                println!("FILE_SYSTEM: new pair with key = {path}, value from {sys_path}");
+                // the old contents are stale, they get reloaded on the next request
+                self.uncache(path);
+                let entry = self.files.entry(path.into()).or_default();
+                entry.size = md.len() as usize;
+                entry.modified = md.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                insert_ancestors(&mut self.dirs, path);
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                println!("FILE_SYSTEM: attemped to sync with nonexistant file at {sys_path}. @suspicious");
+            }
+            Err(e) => {
+                println!("FILE_SYSTEM: failed to read file at {sys_path} because of Error: {e}");
+            }
+        }
+    }
+
+    fn remove(&mut self, path: &Utf8Path) {
+        match self.files.remove(path) {
+            Some(entry) => {
+                if entry.file.is_some() {
+                    self.lru.remove(&entry.last_used);
+                    self.stats.cached -= entry.size;
+                }
+            }
+            None => println!("FILE_SYSTEM: attemped to uncache already uncached file. @suspicious"),
+        }
+    }
+
+    fn stats(&self) -> VfsStats {
+        self.stats
+    }
+}
+
+#[derive(Debug, Clone)]
+struct V_entry {
+    size: usize,
+    modified: SystemTime,
+    file: Option<Rc<V_file>>,
+    last_used: u64,
+}
+
+impl Default for V_entry {
+    fn default() -> Self {
+        Self {
+            size: 0,
+            modified: SystemTime::UNIX_EPOCH,
+            file: None,
+            last_used: 0,
+        }
+    }
+}
+
+/// files held entirely in memory, handy for generated content and tests
+#[derive(Default)]
+pub struct Memory {
+    files: HashMap<Utf8PathBuf, (Rc<V_file>, SystemTime)>,
+    dirs: HashSet<Utf8PathBuf>,
+}
+
+impl Memory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn insert(&mut self, path: impl Into<Utf8PathBuf>, data: impl Into<Vec<u8>>) {
+        let path = path.into();
+        insert_ancestors(&mut self.dirs, &path);
+        self.files.insert(path, (V_file { data: data.into() }.into(), SystemTime::now()));
+    }
+}
+
+impl VfsBackend for Memory {
+    fn get(&mut self, path: &Utf8Path) -> Option<Body> {
+        self.files.get(path).map(|(file, _)| Body::Cached(file.clone()))
+    }
+
+    fn stat(&self, path: &Utf8Path) -> Option<V_stat> {
+        if self.dirs.contains(path) {
+            return Some(V_stat::dir())
+        }
+        self.files.get(path).map(|(file, modified)| V_stat::file(file.data.len(), *modified))
+    }
+
+    fn list(&self, dir: &Utf8Path) -> Vec<V_listing> {
+        let files = self.files.iter().map(|(path, (file, modified))| (path, V_stat::file(file.data.len(), *modified)));
+        list_children(dir, files, &self.dirs)
+    }
+
+    fn remove(&mut self, path: &Utf8Path) {
+        self.files.remove(path);
+    }
+}
+
+/// a read-only ustar archive, file contents are streamed straight out of the archive
+pub struct Tar {
+    archive: Utf8PathBuf,
+    files: HashMap<Utf8PathBuf, TarEntry>,
+    dirs: HashSet<Utf8PathBuf>,
+    stats: VfsStats,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TarEntry {
+    offset: u64,
+    size: usize,
+    modified: SystemTime,
+}
+
+impl Tar {
+    pub fn open(archive: impl Into<Utf8PathBuf>) -> io::Result<Self> {
+        let archive = archive.into();
+        let mut tar = Self {
+            files: HashMap::new(),
+            dirs: HashSet::new(),
+            stats: VfsStats::default(),
+            archive,
+        };
+        tar.dirs.insert("".into());
+        tar.index()?;
+        Ok(tar)
+    }
+
+    //REF: https://www.gnu.org/software/tar/manual/html_node/Standard.html
+    fn index(&mut self) -> io::Result<()> {
+        const BLOCK: u64 = 512;
+        // nothing a real archive needs, but a corrupt size could otherwise ask for any amount of memory
+        const MAX_LONG_NAME: u64 = 4096;
+        const MAX_PAX_HEADER: u64 = 64 << 10;
+
+        let mut file = fs::File::open(&self.archive)?;
+        let mut header = [0u8; BLOCK as usize];
+        let mut offset = 0;
+        let mut long_name: Option<String> = None;
+        let mut pax = Pax::default();
+        loop {
+            file.seek(SeekFrom::Start(offset))?;
+            match file.read_exact(&mut header) {
+                Ok(()) => {},
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            }
+            // the archive ends with two zeroed blocks
+            if header.iter().all(|&b| b == 0) { break }
+
+            let typeflag = header[156];
+            let mut size = parse_octal(&header[124..136]).ok_or(ErrorKind::InvalidData)?;
+            let mut mtime = parse_octal(&header[136..148]).unwrap_or(0);
+            // the pseudo-entries describe the one that follows them
+            if !matches!(typeflag, b'L' | b'x' | b'g') {
+                size = pax.size.take().unwrap_or(size);
+                mtime = pax.mtime.take().unwrap_or(mtime);
+            }
+            let data_offset = offset + BLOCK;
+            offset = data_offset + size.div_ceil(BLOCK) * BLOCK;
+
+            let mut read_data = |max: u64| -> io::Result<Vec<u8>> {
+                if size > max {
+                    return Err(io::Error::new(ErrorKind::InvalidData, format!("a {size} byte tar header is too big")))
+                }
+                let mut data = vec![0; size as usize];
+                file.seek(SeekFrom::Start(data_offset))?;
+                file.read_exact(&mut data)?;
+                Ok(data)
+            };
+            match typeflag {
+                // GNU stores names longer than 100 bytes in a pseudo-entry preceding the real one
+                b'L' => {
+                    long_name = Some(tar_str(&read_data(MAX_LONG_NAME)?).to_owned());
+                    continue
+                },
+                //REF: https://pubs.opengroup.org/onlinepubs/9699919799/utilities/pax.html#tag_20_92_13_03
+                b'x' => {
+                    pax = Pax::parse(&read_data(MAX_PAX_HEADER)?)?;
+                    continue
+                },
+                // global headers only carry defaults we don't use
+                b'g' => continue,
+                _ => {},
+            }
+            let name = match pax.path.take().or(long_name.take()) {
+                Some(name) => name,
+                None => {
+                    let prefix = tar_str(&header[345..500]);
+                    let name = tar_str(&header[0..100]);
+                    match prefix {
+                        "" => name.to_owned(),
+                        prefix => format!("{prefix}/{name}"),
+                    }
+                }
+            };
+            let path: Utf8PathBuf = name.trim_start_matches("./").trim_end_matches('/').into();
+            if path.as_str().is_empty() || path.components().any(|c| c.as_str() == "..") {
+                continue
+            }
+            match typeflag {
+                b'0' | 0 => {
+                    insert_ancestors(&mut self.dirs, &path);
+                    self.files.insert(path, TarEntry {
+                        offset: data_offset,
+                        size: size as usize,
+                        modified: SystemTime::UNIX_EPOCH + Duration::from_secs(mtime),
+                    });
+                }
+                b'5' => {
+                    insert_ancestors(&mut self.dirs, &path);
+                    self.dirs.insert(path);
+                }
+                // links and devices are not served
+                _ => {},
+            }
+        }
+        println!("FILE_SYSTEM: indexed {} files from {}", self.files.len(), self.archive);
+        Ok(())
+    }
+}
+
+/// what a pax extended header says about the entry after it
+#[derive(Default)]
+struct Pax {
+    path: Option<String>,
+    size: Option<u64>,
+    mtime: Option<u64>,
+}
+
+impl Pax {
+    /// records look like "30 path=some/long/file/name.txt\n", the length counting the whole record
+    fn parse(mut data: &[u8]) -> io::Result<Self> {
+        let bad = || io::Error::new(ErrorKind::InvalidData, "malformed pax header");
+        let mut pax = Pax::default();
+        while !data.is_empty() && data[0] != 0 {
+            let space = data.iter().position(|&b| b == b' ').ok_or_else(bad)?;
+            let len: usize = std::str::from_utf8(&data[..space]).ok().and_then(|len| len.parse().ok()).ok_or_else(bad)?;
+            if len <= space + 1 || len > data.len() { return Err(bad()) }
+            let record = std::str::from_utf8(&data[space + 1..len]).map_err(|_| bad())?;
+            let (key, value) = record.strip_suffix('\n').and_then(|record| record.split_once('=')).ok_or_else(bad)?;
+            match key {
+                "path" => pax.path = Some(value.to_owned()),
+                "size" => pax.size = Some(value.parse().map_err(|_| bad())?),
+                // seconds, maybe with a fraction
+                "mtime" => pax.mtime = value.split('.').next().and_then(|secs| secs.parse().ok()),
+                _ => {},
+            }
+            data = &data[len..];
+        }
+        Ok(pax)
+    }
+}
+
+fn tar_str(field: &[u8]) -> &str {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    std::str::from_utf8(&field[..end]).unwrap_or("")
+}
+
+fn parse_octal(field: &[u8]) -> Option<u64> {
+    let digits = tar_str(field).trim();
+    if digits.is_empty() { return Some(0) }
+    u64::from_str_radix(digits, 8).ok()
+}
+
+impl VfsBackend for Tar {
+    fn get(&mut self, path: &Utf8Path) -> Option<Body> {
+        let entry = self.files.get(path)?;
+        match V_stream::region(&self.archive, entry.offset, entry.size as u64) {
+            Ok(stream) => {
+                self.stats.streamed += 1;
+                Some(Body::Streamed(stream))
+            }
+            Err(e) => {
+                println!("FILE_SYSTEM: failed to open {path} in {} because of Error: {e}", self.archive);
+                None
+            }
+        }
+    }
+
+    fn stat(&self, path: &Utf8Path) -> Option<V_stat> {
+        if self.dirs.contains(path) {
+            return Some(V_stat::dir())
+        }
+        self.files.get(path).map(|entry| V_stat::file(entry.size, entry.modified))
+    }
+
+    fn list(&self, dir: &Utf8Path) -> Vec<V_listing> {
+        let files = self.files.iter().map(|(path, entry)| (path, V_stat::file(entry.size, entry.modified)));
+        list_children(dir, files, &self.dirs)
+    }
+
+    fn stats(&self) -> VfsStats {
+        self.stats
+    }
+}
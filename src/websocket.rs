@@ -1,17 +1,259 @@
 use core::{fmt, str};
-use std::{error::Error, io::{self, Write}, mem};
+use std::{cell::OnceCell, error::Error, io::{self, Cursor, Read, Write}, mem, rc::Rc, time::{Duration, Instant}};
 
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress};
 use sha1::{Digest, Sha1};
 use base64::prelude::*;
 
-use crate::{helpers::{Parser, SendTo}, http, server_G::{HandshakeStatus, Handshaker, Server_G}, smithy::{self, HttpSmith, HttpSmithText}, StreamId};
+use crate::{extract::rejection, helpers::{Parser, SendTo}, http::{Content, Method, Request, Response, Status}, server_G::{Adopter, Adoption, HandshakeStatus, Handshaker, Outgoing, SendError, Server_G, Session, Verdict}, smithy::{self, HttpSmith, HttpSmithText}, StreamId, TLS::TLStream};
 
-pub type WsServer = Server_G<Messenger, WsParser, Message, WebSocketError, WsHandshaker>;
+pub type WsServer = Server_G<Messenger, WsParser, Message, WebSocketError, WsHandshaker, WsSession>;
 
 impl WsServer {
-    pub fn send_message(&mut self, id: StreamId, message: impl Into<Message>) {
-        let m: Message = message.into();
-        self.send_to_client(id, m);
+    pub fn send_message(&mut self, id: StreamId, message: impl Into<Message>) -> Result<(), SendError> {
+        let messenger = Messenger::new(message.into(), self.session.max_frame_size);
+        self.queue(id, messenger)
+    }
+    /// sends everything `source` reads as one message, a frame of up to `WsConfig::max_frame_size` at a time, read once
+    /// the last one is out. pings and pongs may go in between, other messages wait. a text stream has to be valid utf-8
+    /// once it's all put together, and a read error drops the client
+    pub fn send_stream(&mut self, id: StreamId, source: impl Read + 'static, text: bool) -> Result<(), SendError> {
+        let opcode = match text { true => OPCODE::Text, false => OPCODE::Binary };
+        let messenger = Messenger::fragmented(opcode, Source::Reader(Box::new(source)), self.session.max_frame_size);
+        self.queue(id, messenger)
+    }
+    /// `send_stream` for chunks that are made as they're needed, frames don't follow the chunk boundaries
+    pub fn send_chunks(&mut self, id: StreamId, chunks: impl Iterator<Item = Vec<u8>> + 'static, text: bool) -> Result<(), SendError> {
+        self.send_stream(id, Chunks { chunks, chunk: Vec::new(), read: 0 }, text)
+    }
+    /// sends to every client done with its handshake, returning the ones it couldn't
+    pub fn broadcast(&mut self, message: impl Into<Message>) -> Vec<(StreamId, SendError)> {
+        let ids = self.clients.values()
+            .filter(|client| !client.is_handshaking)
+            .map(|client| client.id)
+            .collect();
+        self.send_shared(ids, message.into())
+    }
+    /// sends to everyone that joined `room`, returning the ones it couldn't
+    pub fn send_to_room(&mut self, room: &str, message: impl Into<Message>) -> Vec<(StreamId, SendError)> {
+        let ids = self.members(room).collect();
+        self.send_shared(ids, message.into())
+    }
+    /// frames the message once, every client's queue gets the same bytes
+    fn send_shared(&mut self, ids: Vec<StreamId>, message: Message) -> Vec<(StreamId, SendError)> {
+        let messenger = Messenger::new(message, self.session.max_frame_size);
+        ids.into_iter()
+            .filter_map(|id| {
+                // made by `new`, so it has no stream that could only be read once
+                let copy = messenger.share()?;
+                self.queue(id, copy).err().map(|e| (id, e))
+            })
+            .collect()
+    }
+    fn queue(&mut self, id: StreamId, messenger: Messenger) -> Result<(), SendError> {
+        let client = self.clients.get(&id).ok_or(SendError::Gone)?;
+        // nothing may follow our close frame
+        if client.session.closing.is_some() { return Err(SendError::Gone) }
+        self.send_to_client(id, messenger)
+    }
+    /// starts the closing handshake, the client is dropped once it answers or `close_timeout` runs out.
+    /// either way it's reported with `Notification::Disconnected`
+    pub fn close(&mut self, id: StreamId, code: u16, reason: &str) {
+        let Some(client) = self.clients.get_mut(&id) else { return };
+        if client.session.closing.is_some() { return }
+        let frame = Message::Close(CloseFrame::new(code, reason));
+        match self.send_to_client(id, frame) {
+            Ok(()) => {},
+            // a full queue would hold up the close, it's better to go without
+            Err(SendError::QueueFull) => {
+                let frame = CloseFrame::new(CloseFrame::ABNORMAL, "couldn't queue our close");
+                return self.disconnect(id, Message::Close(frame))
+            },
+            Err(SendError::Gone) => return,
+        }
+        if let Some(client) = self.clients.get_mut(&id) {
+            client.session.closing = Some(Instant::now() + self.close_timeout);
+        }
+    }
+    /// the subprotocol agreed on in the handshake
+    pub fn protocol(&self, id: StreamId) -> Option<&str> {
+        self.clients.get(&id)?.session.protocol.as_deref()
+    }
+    /// the round trip of the last ping the client answered
+    pub fn latency(&self, id: StreamId) -> Option<Duration> {
+        self.clients.get(&id)?.session.latency()
+    }
+}
+
+/// set through `Server_G::session`
+#[derive(Debug, Clone)]
+pub struct WsConfig {
+    /// how often clients are pinged, checked on every heartbeat so nothing is sent without `Server_G::heartbeat`
+    pub ping_interval: Option<Duration>,
+    /// pings in a row a client may leave unanswered before it's dropped
+    pub max_missed_pongs: u32,
+    /// payloads bigger than this go out in several frames, which aren't compressed
+    pub max_frame_size: usize,
+    /// the biggest frame a client may send, anything bigger is refused before it's read and the client closed with 1009
+    pub max_incoming_frame: usize,
+    /// the same for a whole message, once its fragments are put together and inflated
+    pub max_incoming_message: usize,
+}
+
+impl WsConfig {
+    fn limits(&self) -> Limits {
+        Limits { frame: self.max_incoming_frame, message: self.max_incoming_message }
+    }
+}
+
+/// how big what a client sends may get, handed to the parser once the handshake is done
+#[derive(Debug, Clone, Copy)]
+struct Limits {
+    frame: usize,
+    message: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        WsConfig::default().limits()
+    }
+}
+
+impl Default for WsConfig {
+    fn default() -> Self {
+        Self {
+            ping_interval: Some(Duration::from_secs(30)),
+            max_missed_pongs: 3,
+            max_frame_size: 1 << 20,
+            max_incoming_frame: 16 << 20,
+            max_incoming_message: 64 << 20,
+        }
+    }
+}
+
+/// answers close frames and pings, and keeps track of the ones we sent
+#[derive(Debug)]
+pub struct WsSession {
+    /// when we give up on the peer answering our close frame
+    closing: Option<Instant>,
+    last_ping: Instant,
+    /// the number in the ping we're waiting on and when it went out
+    pinged: Option<(u64, Instant)>,
+    pings: u64,
+    missed: u32,
+    latency: Option<Duration>,
+    /// payloads at least this big go out compressed, if permessage-deflate was agreed on
+    deflate_from: Option<usize>,
+    protocol: Option<String>,
+    limits: Limits,
+}
+
+impl WsSession {
+    pub fn latency(&self) -> Option<Duration> {
+        self.latency
+    }
+    pub fn protocol(&self) -> Option<&str> {
+        self.protocol.as_deref()
+    }
+}
+
+impl Session<Message, Messenger, WebSocketError> for WsSession {
+    type Config = WsConfig;
+
+    fn new(config: &WsConfig) -> Self {
+        Self {
+            closing: None,
+            last_ping: Instant::now(),
+            pinged: None,
+            pings: 0,
+            missed: 0,
+            latency: None,
+            deflate_from: None,
+            protocol: None,
+            limits: config.limits(),
+        }
+    }
+    fn receive(&mut self, message: Message) -> Verdict<Message, Messenger> {
+        match message {
+            //REF: https://www.rfc-editor.org/rfc/rfc6455#section-5.5.1
+            Message::Close(frame) => match self.closing.take() {
+                // the answer to ours
+                Some(_) => Verdict::Close(None, Message::Close(frame)),
+                None => {
+                    let code = match frame.code {
+                        CloseFrame::NO_STATUS => CloseFrame::NORMAL,
+                        code => code,
+                    };
+                    let echo = Message::Close(CloseFrame::new(code, ""));
+                    Verdict::Close(Some(echo.into()), Message::Close(frame))
+                }
+            },
+            // nothing but the close may follow ours
+            Message::Ping(_) if self.closing.is_some() => Verdict::Ignore,
+            //REF: https://www.rfc-editor.org/rfc/rfc6455#section-5.5.2
+            Message::Ping(data) => Verdict::Reply(Message::Pong(data).into()),
+            // unsolicited pongs are allowed and mean nothing
+            Message::Pong(data) => {
+                if let Some((number, sent)) = self.pinged {
+                    if data == number.to_be_bytes() {
+                        self.latency = Some(sent.elapsed());
+                        self.pinged = None;
+                        self.missed = 0;
+                    }
+                }
+                Verdict::Ignore
+            },
+            message => Verdict::Deliver(message),
+        }
+    }
+    //REF: https://www.rfc-editor.org/rfc/rfc6455#section-7.1.7
+    fn fault(&mut self, error: WebSocketError) -> Verdict<Message, Messenger> {
+        let code = match error {
+            WebSocketError::TOO_BIG => CloseFrame::TOO_BIG,
+            WebSocketError::NOT_VALID_UTF8 => CloseFrame::INVALID_DATA,
+            _ => CloseFrame::PROTOCOL_ERROR,
+        };
+        let frame = CloseFrame::new(code, error.to_string());
+        // ours is already out, there's no saying it twice
+        match self.closing.take() {
+            Some(_) => Verdict::Close(None, Message::Close(frame)),
+            None => Verdict::Close(Some(Message::Close(frame.clone()).into()), Message::Close(frame)),
+        }
+    }
+    fn prepare(&mut self, messenger: &mut Messenger) {
+        if let Some(min_size) = self.deflate_from {
+            messenger.deflate = messenger.can_deflate(min_size);
+        }
+    }
+    fn beat(&mut self, config: &WsConfig, now: Instant) -> Verdict<Message, Messenger> {
+        let Some(interval) = config.ping_interval else { return Verdict::Ignore };
+        if self.closing.is_some() || now.duration_since(self.last_ping) < interval {
+            return Verdict::Ignore
+        }
+        if self.pinged.is_some() {
+            self.missed += 1;
+            if self.missed >= config.max_missed_pongs {
+                let reason = format!("missed {} pongs", self.missed);
+                return Verdict::Close(None, Message::Close(CloseFrame::new(CloseFrame::ABNORMAL, reason)))
+            }
+        }
+        self.pings += 1;
+        self.pinged = Some((self.pings, now));
+        self.last_ping = now;
+        Verdict::Reply(Message::Ping(self.pings.to_be_bytes().to_vec()).into())
+    }
+    fn deadline(&self) -> Option<Instant> {
+        self.closing
+    }
+    fn expire(&mut self, _now: Instant) -> Verdict<Message, Messenger> {
+        match self.closing.take() {
+            Some(_) => Verdict::Close(None, Message::Close(CloseFrame::new(CloseFrame::ABNORMAL, "the peer never answered our close"))),
+            None => Verdict::Ignore,
+        }
+    }
+    //REF: https://www.rfc-editor.org/rfc/rfc6455#section-7.1.5
+    fn lost(&mut self) -> Option<Message> {
+        Some(Message::Close(CloseFrame::new(CloseFrame::ABNORMAL, "the connection was lost")))
     }
 }
 
@@ -21,23 +263,166 @@ pub fn compute_sec_websocket_accept(key: &str) -> String {
     BASE64_STANDARD.encode(sha1.finalize())
 }
 
-#[derive(Default, Clone)]
+/// reads a frame at a time, a frame that's only partly in is left in the buffer until the rest of it comes
+#[derive(Default)]
 pub struct WsParser {
-    incoming_message: Option<Message>,
-    skip: usize,
+    /// the message whose last frame hasn't come in yet
+    partial: Option<Partial>,
+    /// there when permessage-deflate was agreed on, kept across messages in case the client takes its context over
+    inflater: Option<Decompress>,
+    limits: Limits,
+}
+
+/// the fragments of a data message so far, unmasked
+struct Partial {
+    text: bool,
+    deflated: bool,
+    data: Vec<u8>,
 }
 
 impl Parser<Message, WebSocketError> for WsParser {
-    fn parse<'b>(&mut self, buf: &'b [u8]) -> Result<(Option<Message>, &'b [u8]), WebSocketError> {
-        match parse_message(&mut self.incoming_message, &buf[self.skip..]) {
-            Ok((msg, rest)) => {
-                self.skip = 0;
-                Ok((Some(msg), rest))
-            },
-            Err(WebSocketError::WOULD_BLOCK) => Ok((None, buf)),
-            Err(e) => Err(e),
+    /// the frames before a message's last one are taken out of `buf` and stashed, so they're never read twice
+    fn parse<'b>(&mut self, mut buf: &'b [u8]) -> Result<(Option<Message>, &'b [u8]), WebSocketError> {
+        loop {
+            let (frame, rest) = match read_frame(buf, self.payload_room()) {
+                Ok(read) => read,
+                Err(WebSocketError::WOULD_BLOCK) => return Ok((None, buf)),
+                Err(e) => return Err(e),
+            };
+            buf = rest;
+            if let Some(message) = self.take_frame(frame)? {
+                return Ok((Some(message), rest))
+            }
         }
     }
+    fn room(&self) -> usize {
+        //REF: https://www.rfc-editor.org/rfc/rfc6455#section-5.2
+        const MAX_HEAD: usize = 2 + 8 + 4;
+        // control frames may come between fragments whatever room is left
+        self.payload_room().max(125) + MAX_HEAD
+    }
+}
+
+/// set through `Server_G::handshake`
+#[derive(Debug, Clone)]
+pub struct WsHandshakeConfig {
+    /// offered to clients that ask for permessage-deflate, None to turn it down
+    pub deflate: Option<DeflateConfig>,
+    /// the only `Origin`s allowed to connect, e.g. `https://example.com`, so other sites can't open sockets
+    /// with their visitors' cookies. clients that send no origin are refused too. None allows anyone
+    pub origins: Option<Vec<String>>,
+    /// subprotocols we speak, the first one the client also offers is picked
+    pub protocols: Vec<String>,
+}
+
+impl Default for WsHandshakeConfig {
+    fn default() -> Self {
+        Self {
+            deflate: Some(DeflateConfig::default()),
+            origins: None,
+            protocols: Vec::new(),
+        }
+    }
+}
+
+/// what a handshake settled on
+#[derive(Debug, Clone, Default)]
+pub struct Agreement {
+    pub protocol: Option<String>,
+    pub deflate: Option<DeflateConfig>,
+}
+
+/// checks an upgrade request, giving the 101 to answer it with or the response to refuse it with
+pub fn upgrade(request: &Request, config: &WsHandshakeConfig) -> Result<(Response, Agreement), Response> {
+    //REF: https://www.rfc-editor.org/rfc/rfc6455#section-4.2.1
+    let header = |key: &str| request.headers.get(key).map(String::as_str);
+    let has_token = |key: &str, token: &str| header(key).is_some_and(|value| {
+        value.split(',').any(|item| item.trim().eq_ignore_ascii_case(token))
+    });
+    let refuse = |status: Status, why: &str| {
+        println!("WEBSOCKET: refused a handshake, {why}");
+        rejection(status, why)
+    };
+
+    if request.method != Method::GET {
+        return Err(refuse(Status::BadRequest, "the handshake has to be a GET"))
+    }
+    if !has_token("upgrade", "websocket") {
+        let mut response = refuse(Status::UpgradeRequired, "this endpoint only speaks websocket");
+        response.add_header("Upgrade", "websocket");
+        response.add_header("Connection", "Upgrade");
+        return Err(response)
+    }
+    if !has_token("connection", "upgrade") {
+        return Err(refuse(Status::BadRequest, "the connection header doesn't ask for an upgrade"))
+    }
+    if header("sec-websocket-version") != Some("13") {
+        let mut response = refuse(Status::UpgradeRequired, "only version 13 is supported");
+        response.add_header("Sec-WebSocket-Version", "13");
+        return Err(response)
+    }
+    let key = header("sec-websocket-key").unwrap_or("");
+    if BASE64_STANDARD.decode(key).map_or(true, |nonce| nonce.len() != 16) {
+        return Err(refuse(Status::BadRequest, "the key isn't a base64 encoded 16 byte nonce"))
+    }
+    if let Some(origins) = &config.origins {
+        let origin = header("origin").unwrap_or("");
+        if !origins.iter().any(|allowed| allowed.eq_ignore_ascii_case(origin)) {
+            return Err(refuse(Status::Forbidden, &format!("the origin '{origin}' isn't allowed")))
+        }
+    }
+
+    let mut response: Response = Status::SwitchingProtocols.into();
+    response.add_header("Upgrade", "websocket");
+    response.add_header("Connection", "Upgrade");
+    response.add_header("Sec-WebSocket-Accept", &compute_sec_websocket_accept(key));
+
+    let mut agreement = Agreement::default();
+    //REF: https://www.rfc-editor.org/rfc/rfc6455#section-11.3.4
+    if let Some(offered) = header("sec-websocket-protocol") {
+        let offered: Vec<&str> = offered.split(',').map(str::trim).collect();
+        if let Some(protocol) = config.protocols.iter().find(|protocol| offered.contains(&protocol.as_str())) {
+            response.add_header("Sec-WebSocket-Protocol", protocol);
+            agreement.protocol = Some(protocol.clone());
+        }
+    }
+    if let (Some(offers), Some(deflate)) = (header("sec-websocket-extensions"), &config.deflate) {
+        if let Some(agreed) = negotiate_deflate(offers, deflate) {
+            response.add_header("Sec-WebSocket-Extensions", &agreed);
+            agreement.deflate = Some(deflate.clone());
+        }
+    }
+    Ok((response, agreement))
+}
+
+/// an upgrade an `HttpServer` route agreed to, see `HttpServer::add_websocket`
+pub struct Upgrade {
+    pub agreement: Agreement,
+    pub adopter: Adopter<WsHandshaker>,
+}
+
+impl Upgrade {
+    /// the `WsServer` sends `head`, the serialized 101, and takes it from there. false if it's gone
+    pub fn hand_over(self, stream: TLStream, head: Vec<u8>, leftover: Vec<u8>) -> bool {
+        let handshaker = WsHandshaker { buf: head, agreement: self.agreement, ..WsHandshaker::default() };
+        self.adopter.adopt(Adoption { stream, handshaker, leftover })
+    }
+}
+
+//REF: https://www.rfc-editor.org/rfc/rfc7692
+/// we always compress without context takeover, so a compressed frame is the same for every client and can still be broadcast
+#[derive(Debug, Clone)]
+pub struct DeflateConfig {
+    /// messages with smaller payloads go out uncompressed
+    pub min_size: usize,
+    /// asked of clients that offer `client_max_window_bits`, they're free to use less
+    pub client_max_window_bits: Option<u8>,
+}
+
+impl Default for DeflateConfig {
+    fn default() -> Self {
+        Self { min_size: 128, client_max_window_bits: None }
+    }
 }
 
 #[derive(Debug, Default, Clone)]
@@ -45,6 +430,11 @@ pub struct WsHandshaker {
     buf: Vec<u8>,
     bytes_writ: usize,
     parser: HttpSmithText,
+    config: WsHandshakeConfig,
+    /// what we agreed to, once we have
+    agreement: Agreement,
+    /// the response is a refusal, the client is dropped once it's out
+    refused: bool,
 }
 
 impl SendTo for WsHandshaker {
@@ -58,7 +448,20 @@ impl SendTo for WsHandshaker {
     }
 }
 
-impl Handshaker for WsHandshaker {
+impl Handshaker<WsParser, WsSession> for WsHandshaker {
+    type Config = WsHandshakeConfig;
+
+    fn new(config: &WsHandshakeConfig) -> Self {
+        Self { config: config.clone(), ..Self::default() }
+    }
+    fn finish(&mut self, parser: &mut WsParser, session: &mut WsSession) {
+        if let Some(deflate) = &self.agreement.deflate {
+            parser.inflater = Some(Decompress::new(false));
+            session.deflate_from = Some(deflate.min_size);
+        }
+        session.protocol = self.agreement.protocol.take();
+        parser.limits = session.limits;
+    }
     fn handshake<'b>(&mut self, buf: &'b [u8]) -> Option<(crate::server_G::HandshakeStatus, &'b [u8])> {
         let len = self.buf.len();
         debug_assert!(self.bytes_writ <= len);
@@ -67,20 +470,38 @@ impl Handshaker for WsHandshaker {
             if self.bytes_writ < len {
                 return Some((HandshakeStatus::Responding, buf))
             }
+            else if self.refused {
+                return None
+            }
             else {
                 return Some((HandshakeStatus::Done, buf))
             }
         }
         match self.parser.deserialize(buf) {
             Ok((handshake, rest)) => {
-                let key = handshake.headers.get("sec-websocket-key")?;
-                let accept = compute_sec_websocket_accept(key);
-                let mut response: http::Response = http::Status::SwitchingProtocols.into();
-                response.add_header("Upgrade", "websocket");
-                response.add_header("Connection", "Upgrade");
-                // TODO: unecessary clone
-                response.add_header("Sec-WebSocket-Accept", &accept);
-                let (data, _) = self.parser.serialize(&response);
+                let mut response = match upgrade(&handshake, &self.config) {
+                    Ok((response, agreement)) => {
+                        self.agreement = agreement;
+                        response
+                    },
+                    Err(response) => {
+                        self.refused = true;
+                        response
+                    },
+                };
+                // there's no one to finish the response for us
+                let body = match mem::replace(&mut response.body, Content::Empty) {
+                    Content::Bytes(body) => body,
+                    _ => Vec::new(),
+                };
+                if self.refused {
+                    response.add_header("Content-Length", &body.len().to_string());
+                    if !response.headers.iter().any(|header| header.0.eq_ignore_ascii_case("connection")) {
+                        response.add_header("Connection", "close");
+                    }
+                }
+                let mut data = self.parser.serialize(&response);
+                data.extend_from_slice(&body);
                 println!("WEBSOCKET: handshake response length = {}", data.len());
                 self.buf = data;
                 Some((HandshakeStatus::Responding, rest))
@@ -94,49 +515,141 @@ impl Handshaker for WsHandshaker {
     }
 }
 
-fn parse_message<'b>(incoming_message: &mut Option<Message>, mut buf: &'b [u8]) -> Result<(Message, &'b [u8]), WebSocketError> {
-    use OPCODE::*;
-    use WebSocketError::*;
-    loop {
-        let (frame, rest) = read_frame(buf)?;
-        buf = rest;
-        match frame.opcode {
-            Continuation => {
-                if let Some(ref mut message) = incoming_message {
-                    *incoming_message = match message {
-                        Message::Text(t) => Message::Text(frame.unmask_into_text(mem::take(t))?),
-                        Message::Binary(b) => Message::Binary(frame.unmask_into_binary(mem::take(b))),
-                    }.into();
-                    if frame.fin { 
-                        return Ok((incoming_message.take().unwrap(), rest));
+/// picks the first permessage-deflate offer we can live with, returning what to answer with
+fn negotiate_deflate(offers: &str, config: &DeflateConfig) -> Option<String> {
+    //REF: https://www.rfc-editor.org/rfc/rfc7692#section-7.1
+    'offers: for offer in offers.split(',') {
+        let mut params = offer.split(';').map(str::trim);
+        if params.next() != Some("permessage-deflate") { continue }
+
+        // context takeover on our side would make every client's frames different
+        let mut agreed = String::from("permessage-deflate; server_no_context_takeover");
+        let mut seen = Vec::new();
+        for param in params {
+            let (key, value) = match param.split_once('=') {
+                Some((key, value)) => (key.trim(), Some(value.trim().trim_matches('"'))),
+                None => (param, None),
+            };
+            if seen.contains(&key) { continue 'offers }
+            seen.push(key);
+
+            let bits = value.map(|value| value.parse::<u8>().ok().filter(|bits| (8..=15).contains(bits)));
+            match (key, bits) {
+                ("server_no_context_takeover" | "client_no_context_takeover", None) => {},
+                // our compressor always uses the whole window
+                ("server_max_window_bits", Some(Some(15))) => {},
+                ("client_max_window_bits", None | Some(Some(_))) => {
+                    if let Some(ours) = config.client_max_window_bits {
+                        let theirs = bits.flatten().unwrap_or(15);
+                        agreed.push_str(&format!("; client_max_window_bits={}", ours.min(theirs)));
                     }
-                    continue
-                }
-                else { return Err(BAD_CONTINUE) }
+                },
+                _ => continue 'offers,
             }
-            Text => {
-                if incoming_message.is_some() { return Err(CUTTING_IN) }
-                *incoming_message = Some(Message::Text(frame.unmask_into_text(String::with_capacity(256))?));
-                if frame.fin { 
-                    return Ok((incoming_message.take().unwrap(), rest)); 
-                }
-                else { continue }
+        }
+        return Some(agreed)
+    }
+    None
+}
+
+impl WsParser {
+    /// the largest payload the next data frame may have
+    fn payload_room(&self) -> usize {
+        let assembled = self.partial.as_ref().map_or(0, |partial| partial.data.len());
+        self.limits.frame.min(self.limits.message.saturating_sub(assembled))
+    }
+    /// the message `frame` finished, if any
+    fn take_frame(&mut self, frame: Frame) -> Result<Option<Message>, WebSocketError> {
+        use OPCODE::*;
+        use WebSocketError::*;
+
+        // only the first frame of a data message may say it's compressed
+        if frame.rsv1 && !(matches!(frame.opcode, Text | Binary) && self.inflater.is_some()) {
+            return Err(UNRESERVED)
+        }
+        let partial = match frame.opcode {
+            Continuation => self.partial.as_mut().ok_or(BAD_CONTINUE)?,
+            Text | Binary => {
+                if self.partial.is_some() { return Err(CUTTING_IN) }
+                let text = matches!(frame.opcode, Text);
+                self.partial.insert(Partial { text, deflated: frame.rsv1, data: Vec::with_capacity(frame.payload.len()) })
             },
-            Binary => {
-                if incoming_message.is_some() { return Err(CUTTING_IN) }
-                *incoming_message = Some(Message::Binary(frame.unmask_into_binary(Vec::with_capacity(256))));
-                if frame.fin { 
-                    return Ok((incoming_message.take().unwrap(), rest));
-                }
-                else { continue }
+            Close => {
+                let payload = frame.unmask_into(Vec::new());
+                return Ok(Some(Message::Close(CloseFrame::parse(&payload)?)))
+            },
+            // control frames may come in between the fragments of a message, which is left stashed
+            Ping => return Ok(Some(Message::Ping(frame.unmask_into(Vec::new())))),
+            Pong => return Ok(Some(Message::Pong(frame.unmask_into(Vec::new())))),
+        };
+        partial.data = frame.unmask_into(mem::take(&mut partial.data));
+        if !frame.fin { return Ok(None) }
+
+        let Partial { text, deflated, data } = self.partial.take().unwrap();
+        let data = match deflated {
+            true => inflate(self.inflater.as_mut().unwrap(), data, self.limits.message)?,
+            false => data,
+        };
+        // text is only checked once it's all in, a character may be split between fragments
+        match text {
+            true => String::from_utf8(data).map(|text| Some(Message::Text(text))).map_err(|_| NOT_VALID_UTF8),
+            false => Ok(Some(Message::Binary(data))),
+        }
+    }
+}
+
+/// refuses to inflate past `limit`
+fn inflate(inflater: &mut Decompress, mut data: Vec<u8>, limit: usize) -> Result<Vec<u8>, WebSocketError> {
+    //REF: https://www.rfc-editor.org/rfc/rfc7692#section-7.2.2
+    data.extend_from_slice(&[0, 0, 0xff, 0xff]);
+    let start = inflater.total_in();
+    // one byte over the limit is as far as it needs to get to be refused
+    let most = limit.saturating_add(1);
+    let mut inflated = Vec::with_capacity(data.len().saturating_mul(4).min(most));
+    loop {
+        let consumed = (inflater.total_in() - start) as usize;
+        let produced = inflated.len();
+        let status = inflater.decompress_vec(&data[consumed..], &mut inflated, FlushDecompress::Sync)
+            .map_err(|_| WebSocketError::BAD_DEFLATE)?;
+        if inflated.len() > limit {
+            return Err(WebSocketError::TOO_BIG)
+        }
+        // a client without context takeover may end each message with a final block
+        if status == flate2::Status::StreamEnd {
+            inflater.reset(false);
+            return Ok(inflated)
+        }
+        if inflated.len() < inflated.capacity() {
+            // the decompressor may stop short with room to spare, it's only stuck if it did nothing
+            match (inflater.total_in() - start) as usize {
+                all if all == data.len() => return Ok(inflated),
+                now if now == consumed && inflated.len() == produced => return Err(WebSocketError::BAD_DEFLATE),
+                _ => continue,
             }
-            Close => { return Err(UNIMPLEMENTED) },
-            Ping => { return Err(UNIMPLEMENTED) },
-            Pong => { return Err(UNIMPLEMENTED) },
         }
+        inflated.reserve_exact(inflated.capacity().min(most - inflated.len()));
+    }
+}
+
+fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut compressor = Compress::new(Compression::default(), false);
+    let mut deflated = Vec::with_capacity(data.len() / 2 + 64);
+    loop {
+        let consumed = compressor.total_in() as usize;
+        // only fails when fed a broken stream
+        compressor.compress_vec(&data[consumed..], &mut deflated, FlushCompress::Sync).unwrap();
+        if compressor.total_in() as usize == data.len() && deflated.len() < deflated.capacity() { break }
+        deflated.reserve(deflated.capacity());
+    }
+    //REF: https://www.rfc-editor.org/rfc/rfc7692#section-7.2.1
+    if deflated.ends_with(&[0, 0, 0xff, 0xff]) {
+        deflated.truncate(deflated.len() - 4);
     }
+    deflated
 }
-fn read_frame(data: &[u8]) -> Result<(Frame, &[u8]), WebSocketError> {
+
+/// `room` is how big a data frame may be, control frames have their own limit
+fn read_frame(data: &[u8], room: usize) -> Result<(Frame<'_>, &[u8]), WebSocketError> {
     use WebSocketError::*;
 
     let mut mask_offset = 1 + 1;
@@ -144,7 +657,9 @@ fn read_frame(data: &[u8]) -> Result<(Frame, &[u8]), WebSocketError> {
     if data.len() < (mask_offset + mask_len) { return Err(WOULD_BLOCK) }
 
     let fin = data[0] & 0b1000_0000 > 0;
-    if (data[0] & 0b0111_0000) != 0 { return Err(UNRESERVED) }
+    // rsv1 is permessage-deflate's, whether it may be set is up to the parser
+    let rsv1 = data[0] & 0b0100_0000 > 0;
+    if (data[0] & 0b0011_0000) != 0 { return Err(UNRESERVED) }
     let opcode = OPCODE::parse(data[0] & 0b_1111)?;
     if (data[1] & 0b1000_0000) == 0 { return Err(UNMASKED) }
     let payload_len = match (data[1] & 0b0111_1111) {
@@ -161,6 +676,13 @@ fn read_frame(data: &[u8]) -> Result<(Frame, &[u8]), WebSocketError> {
         }
         _ => unreachable!(),
     };
+    //REF: https://www.rfc-editor.org/rfc/rfc6455#section-5.5
+    if opcode.is_control() {
+        if !fin || payload_len > 125 { return Err(BAD_CONTROL) }
+    }
+    else if payload_len > room as u64 {
+        return Err(TOO_BIG)
+    }
     let mask_key: [u8;4] = data[mask_offset..][..mask_len].try_into().unwrap();
     let payload = &data[mask_offset + mask_len..];
     println!("PAYLOAD_LENGTH = {payload_len}, ACTUAL_LENGTH = {}", payload.len());
@@ -168,6 +690,7 @@ fn read_frame(data: &[u8]) -> Result<(Frame, &[u8]), WebSocketError> {
 
     let frame = Frame {
         fin,
+        rsv1,
         opcode,
         mask_key: Some(mask_key),
         payload,
@@ -190,6 +713,11 @@ pub enum WebSocketError {
     CLOSED_BY_CLIENT,
     WOULD_BLOCK,
     WET_HANDSHAKE,
+    BAD_CLOSE,
+    BAD_DEFLATE,
+    /// a fragmented control frame or one with more than 125 bytes
+    BAD_CONTROL,
+    TOO_BIG,
     UNIMPLEMENTED,
 }
 
@@ -212,6 +740,9 @@ enum OPCODE {
 }
 
 impl OPCODE {
+    fn is_control(self) -> bool {
+        (self as u8) & 0b1000 != 0
+    }
     fn parse(value: u8) -> Result<Self, WebSocketError> {
         use OPCODE::*;
         use WebSocketError::*;
@@ -231,46 +762,141 @@ impl OPCODE {
 
 struct Frame<'buf> {
     pub fin: bool,
+    pub rsv1: bool,
     pub opcode: OPCODE,
     pub mask_key: Option<[u8;4]>,
     pub payload: &'buf [u8],
 }
 
 impl Frame<'_> {
-    pub fn unmask_into_binary(&self, mut buffer: Vec<u8>) -> Vec<u8> {
-        assert!(buffer.len() <= self.payload.len());
-
-        if let Some(mask_key) = self.mask_key {
-            for (index, byte)  in self.payload.iter().enumerate() {
-                buffer.push(byte ^ mask_key[index % 4])
-            }
-        } else {
-            buffer.append(&mut self.payload.to_owned());
+    /// appends the unmasked payload to `buffer`
+    pub fn unmask_into(&self, mut buffer: Vec<u8>) -> Vec<u8> {
+        match self.mask_key {
+            Some(mask_key) => buffer.extend(self.payload.iter().enumerate().map(|(index, byte)| byte ^ mask_key[index % 4])),
+            None => buffer.extend_from_slice(self.payload),
         }
         buffer
     }
-    pub fn unmask_into_text(&self, buffer: String) -> Result<String, WebSocketError> {
-        use WebSocketError::*;
-        assert!(buffer.len() <= self.payload.len());
-
-        let mut buffer = buffer.into_bytes();
-        let initial_length = buffer.len();
-        if let Some(mask_key) = self.mask_key {
-            for (index, byte)  in self.payload.iter().enumerate() {
-                buffer.push(byte ^ mask_key[index % 4])
-            }
-        } else {
-            buffer.append(&mut self.payload.to_owned());
-        }
-        let _ = str::from_utf8(&buffer[initial_length..]).map_err(|_e| NOT_VALID_UTF8)?;
-        return Ok( unsafe {String::from_utf8_unchecked(buffer)} )
-    }    
 }
 
 #[derive(Debug, Clone)]
 pub enum Message {
     Text(String),
     Binary(Vec<u8>),
+    Close(CloseFrame),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+}
+
+impl Message {
+    fn opcode(&self) -> OPCODE {
+        match self {
+            Message::Text(_) => OPCODE::Text,
+            Message::Binary(_) => OPCODE::Binary,
+            Message::Close(_) => OPCODE::Close,
+            Message::Ping(_) => OPCODE::Ping,
+            Message::Pong(_) => OPCODE::Pong,
+        }
+    }
+    fn payload_len(&self) -> usize {
+        match self {
+            Message::Text(text) => text.len(),
+            Message::Binary(data) | Message::Ping(data) | Message::Pong(data) => data.len(),
+            Message::Close(frame) => frame.payload().len(),
+        }
+    }
+    /// the whole frame, header and all, servers don't mask
+    fn encode(&self) -> Vec<u8> {
+        let close;
+        let payload = match self {
+            Message::Text(text) => text.as_bytes(),
+            Message::Binary(data) | Message::Ping(data) | Message::Pong(data) => data,
+            Message::Close(frame) => {
+                close = frame.payload();
+                &close
+            }
+        };
+        encode_frame((1 << 7) | (self.opcode() as u8), payload)
+    }
+}
+
+fn encode_frame(head: u8, payload: &[u8]) -> Vec<u8> {
+    const limit1: usize = 126;
+    const limit2: usize = 1 << 16;
+
+    let len = payload.len();
+    let mut data = Vec::with_capacity(2 + 8 + len);
+    data.push(head);
+    match len {
+        0..limit1 => data.push(len as u8),
+        limit1..limit2 => {
+            data.push(126);
+            data.extend_from_slice(&(len as u16).to_be_bytes());
+        },
+        limit2.. => {
+            data.push(127);
+            data.extend_from_slice(&(len as u64).to_be_bytes());
+        },
+    }
+    data.extend_from_slice(payload);
+    data
+}
+
+/// the status code and reason of a close frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloseFrame {
+    pub code: u16,
+    pub reason: String,
+}
+
+//REF: https://www.rfc-editor.org/rfc/rfc6455#section-7.4.1
+impl CloseFrame {
+    pub const NORMAL: u16 = 1000;
+    pub const GOING_AWAY: u16 = 1001;
+    pub const PROTOCOL_ERROR: u16 = 1002;
+    pub const UNSUPPORTED: u16 = 1003;
+    /// reported for close frames without a code, never sent
+    pub const NO_STATUS: u16 = 1005;
+    /// reported when the connection went away without a close frame, never sent
+    pub const ABNORMAL: u16 = 1006;
+    pub const INVALID_DATA: u16 = 1007;
+    pub const POLICY: u16 = 1008;
+    pub const TOO_BIG: u16 = 1009;
+    pub const INTERNAL_ERROR: u16 = 1011;
+
+    pub fn new(code: u16, reason: impl Into<String>) -> Self {
+        Self { code, reason: reason.into() }
+    }
+    fn parse(payload: &[u8]) -> Result<Self, WebSocketError> {
+        use WebSocketError::*;
+
+        match payload {
+            [] => Ok(Self::new(Self::NO_STATUS, "")),
+            [_] => Err(BAD_CLOSE),
+            [high, low, reason @ ..] => {
+                let code = u16::from_be_bytes([*high, *low]);
+                let reserved = matches!(code, 1004..=1006 | 1015);
+                let unassigned = matches!(code, 0..=999 | 1016..=2999 | 5000..);
+                if reserved || unassigned { return Err(BAD_CLOSE) }
+                let reason = str::from_utf8(reason).map_err(|_| NOT_VALID_UTF8)?;
+                Ok(Self::new(code, reason))
+            }
+        }
+    }
+    /// codes that only exist to be reported go out as an empty close frame
+    fn payload(&self) -> Vec<u8> {
+        if matches!(self.code, Self::NO_STATUS | Self::ABNORMAL | 1015) {
+            return Vec::new()
+        }
+        // control frames carry 125 bytes at most
+        let mut end = self.reason.len().min(123);
+        while !self.reason.is_char_boundary(end) {
+            end -= 1;
+        }
+        let mut payload = self.code.to_be_bytes().to_vec();
+        payload.extend_from_slice(&self.reason.as_bytes()[..end]);
+        payload
+    }
 }
 
 impl Default for Message {
@@ -290,34 +916,159 @@ impl From<String> for Message {
     }
 }
 
-impl<'a> From<&'a Message> for Frame<'a> {
-    fn from(msg: &'a Message) -> Self {
-        match msg {
-            Message::Binary(contents) => Frame {
-                fin: true,
-                opcode: OPCODE::Binary,
-                mask_key: None,
-                payload: contents,
-            },
-            Message::Text(contents) => Frame { 
-                fin: true, 
-                opcode: OPCODE::Text, 
-                mask_key: None, 
-                payload: contents.as_bytes(),
-            }
+#[derive(Debug, Default)]
+pub struct Messenger {
+    /// shared between the clients a message is broadcast to
+    frame: Rc<Encoded>,
+    /// whether this client gets the compressed frame
+    deflate: bool,
+    writ: usize,
+    /// where the frames after this one come from, if the message goes out in pieces
+    rest: Option<Box<Fragments>>,
+}
+
+/// a frame ready to go out, compressed the first time a client wants it that way
+#[derive(Debug, Default)]
+struct Encoded {
+    plain: Vec<u8>,
+    /// where the payload starts in `plain`
+    header: usize,
+    deflated: OnceCell<Vec<u8>>,
+}
+
+/// the frames of a message too big for one, made one at a time
+#[derive(Debug)]
+struct Fragments {
+    /// the first frame's, the rest are continuations
+    opcode: OPCODE,
+    source: Source,
+    max_frame_size: usize,
+}
+
+enum Source {
+    /// a message that's already in memory, shared by everyone it's sent to
+    Shared(Cursor<Rc<[u8]>>),
+    Reader(Box<dyn Read>),
+}
+
+/// reads through the chunks one after the other
+struct Chunks<I> {
+    chunks: I,
+    chunk: Vec<u8>,
+    read: usize,
+}
+
+impl<I: Iterator<Item = Vec<u8>>> Read for Chunks<I> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.read == self.chunk.len() {
+            let Some(chunk) = self.chunks.next() else { return Ok(0) };
+            self.chunk = chunk;
+            self.read = 0;
         }
+        let bytes = (&self.chunk[self.read..]).read(buf)?;
+        self.read += bytes;
+        Ok(bytes)
     }
 }
 
-#[derive(Debug, Default, Clone)]
-pub struct Messenger {
-    msg: Message,
-    writ: usize,
+impl fmt::Debug for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Shared(cursor) => write!(f, "Shared({}/{})", cursor.position(), cursor.get_ref().len()),
+            Source::Reader(_) => write!(f, "Reader"),
+        }
+    }
+}
+
+impl Fragments {
+    /// payload not framed yet, a stream's length isn't known so it counts for a frame
+    fn pending(&self) -> usize {
+        match &self.source {
+            Source::Shared(cursor) => cursor.get_ref().len() - cursor.position() as usize,
+            Source::Reader(_) => self.max_frame_size,
+        }
+    }
+    fn next_frame(&mut self) -> io::Result<Messenger> {
+        let mut payload = Vec::with_capacity(self.max_frame_size);
+        let fin = match &mut self.source {
+            Source::Shared(cursor) => {
+                cursor.by_ref().take(self.max_frame_size as u64).read_to_end(&mut payload)?;
+                cursor.position() as usize == cursor.get_ref().len()
+            },
+            // it's only known to be over when it comes up short, which may take an empty frame
+            Source::Reader(reader) => {
+                reader.by_ref().take(self.max_frame_size as u64).read_to_end(&mut payload)?;
+                payload.len() < self.max_frame_size
+            },
+        };
+        let head = ((fin as u8) << 7) | (mem::replace(&mut self.opcode, OPCODE::Continuation) as u8);
+        let plain = encode_frame(head, &payload);
+        let frame = Encoded {
+            header: plain.len() - payload.len(),
+            plain,
+            deflated: OnceCell::new(),
+        };
+        Ok(Messenger { frame: Rc::new(frame), deflate: false, writ: 0, rest: None })
+    }
+}
+
+impl Messenger {
+    /// text and binary messages bigger than `max_frame_size` go out in pieces
+    fn new(message: Message, max_frame_size: usize) -> Self {
+        if message.payload_len() <= max_frame_size { return message.into() }
+        let opcode = message.opcode();
+        let payload: Rc<[u8]> = match message {
+            Message::Text(text) => text.into_bytes().into(),
+            Message::Binary(data) => data.into(),
+            // control frames are never that big
+            message => return message.into(),
+        };
+        Self::fragmented(opcode, Source::Shared(Cursor::new(payload)), max_frame_size)
+    }
+    /// a copy for another client sharing the frame and the payload left to frame,
+    /// None if the rest comes from a stream, which can only be read once
+    fn share(&self) -> Option<Self> {
+        let rest = match &self.rest {
+            Some(fragments) => match &fragments.source {
+                Source::Shared(cursor) => Some(Box::new(Fragments { source: Source::Shared(cursor.clone()), ..**fragments })),
+                Source::Reader(_) => return None,
+            },
+            None => None,
+        };
+        Some(Self { frame: self.frame.clone(), deflate: self.deflate, writ: self.writ, rest })
+    }
+    /// starts out empty, the first frame is made when it's this message's turn
+    fn fragmented(opcode: OPCODE, source: Source, max_frame_size: usize) -> Self {
+        // an empty frame would never make room for the next
+        let max_frame_size = max_frame_size.max(1);
+        let fragments = Fragments { opcode, source, max_frame_size };
+        Self { rest: Some(Box::new(fragments)), ..Self::default() }
+    }
+    fn bytes(&self) -> &[u8] {
+        let frame = &*self.frame;
+        match self.deflate {
+            //REF: https://www.rfc-editor.org/rfc/rfc7692#section-6
+            true => frame.deflated.get_or_init(|| encode_frame(frame.plain[0] | 0b0100_0000, &deflate(&frame.plain[frame.header..]))),
+            false => &frame.plain,
+        }
+    }
+    /// control frames and fragments are never compressed
+    fn can_deflate(&self, min_size: usize) -> bool {
+        let frame = &*self.frame;
+        let data = matches!(frame.plain.first(), Some(0b1000_0001 | 0b1000_0010));
+        data && frame.plain.len() - frame.header >= min_size
+    }
 }
 
 impl From<Message> for Messenger {
     fn from(value: Message) -> Self {
-        Self { msg: value, writ: 0 }
+        let plain = value.encode();
+        let frame = Encoded {
+            header: plain.len() - value.payload_len(),
+            plain,
+            deflated: OnceCell::new(),
+        };
+        Self { frame: Rc::new(frame), deflate: false, writ: 0, rest: None }
     }
 }
 impl From<Vec<u8>> for Messenger {
@@ -329,39 +1080,132 @@ impl From<Vec<u8>> for Messenger {
 
 impl SendTo for Messenger {
     fn send_to(&mut self, wr: &mut impl Write) -> io::Result<usize> {
-        let f: Frame = (&self.msg).into();
+        let bytes = wr.write(&self.bytes()[self.writ..])?;
+        self.writ += bytes;
+        Ok(bytes)
+    }
+}
 
-        const limit1: usize = 126;
-        const limit2: usize = 1 << 16;
+impl Outgoing for Messenger {
+    fn remaining(&self) -> usize {
+        self.bytes().len() - self.writ
+    }
+    fn footprint(&self) -> usize {
+        self.remaining() + self.rest.as_ref().map_or(0, |fragments| fragments.pending())
+    }
+    /// pings and pongs
+    fn urgent(&self) -> bool {
+        matches!(self.frame.plain.first().map(|head| head & 0b1111), Some(0b1001 | 0b1010))
+    }
+    fn next_part(&mut self) -> io::Result<Option<Self>> {
+        let Some(mut fragments) = self.rest.take() else { return Ok(None) };
+        let mut part = fragments.next_frame()?;
+        if part.frame.plain[0] & 0b1000_0000 == 0 {
+            part.rest = Some(fragments);
+        }
+        Ok(Some(part))
+    }
+}
 
-        let len = f.payload.len();
-        let header_len = match len {
-            0..limit1 => 2,
-            limit1..limit2 => 2 + 2,
-            limit2.. => 2 + 8,
-        };
-        let bytes = if self.writ < header_len {
-            let mut header = [0; 2 + 8];
-            header[0] = (1 << 7) | (f.opcode as u8);
-            header[1] = match header_len {
-                2 => len as u8,
-                4 => {
-                    header[2..][..2].copy_from_slice(&(len as u16).to_be_bytes());
-                    126
-                },
-                10 => {
-                    header[2..][..8].copy_from_slice(&(len as u64).to_be_bytes());
-                    127
-                },
-                _ => unreachable!()
-            };
-            wr.write(&header[self.writ..header_len])?
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a frame as a client would send it, masked
+    fn client_frame(head: u8, payload: &[u8]) -> Vec<u8> {
+        let mask_key = [0x37, 0xfa, 0x21, 0x3d];
+        let mut frame = encode_frame(head, payload);
+        let header = frame.len() - payload.len();
+        frame[1] |= 0b1000_0000;
+        frame.truncate(header);
+        frame.extend_from_slice(&mask_key);
+        frame.extend(payload.iter().enumerate().map(|(index, byte)| byte ^ mask_key[index % 4]));
+        frame
+    }
+    /// what `Server_G::read_messages` does with the buffer, a byte at a time
+    fn feed_bytewise(parser: &mut WsParser, data: &[u8]) -> Result<Vec<Message>, WebSocketError> {
+        let mut buf = Vec::new();
+        let mut messages = Vec::new();
+        for byte in data {
+            buf.push(*byte);
+            loop {
+                let (message, rest) = parser.parse(&buf)?;
+                buf = rest.to_vec();
+                match message {
+                    Some(message) => messages.push(message),
+                    None => break,
+                }
+            }
         }
-        else {
-            wr.write(&f.payload[self.writ - header_len..])?
-        };
+        assert!(buf.is_empty(), "{} bytes left over", buf.len());
+        Ok(messages)
+    }
 
-        self.writ += bytes;
-        Ok(bytes)
+    #[test]
+    fn fragmented_text_with_a_ping_in_between() {
+        // the é is split between the first two fragments
+        let text = "héllo wörld".as_bytes();
+        let mut data = client_frame(0b0000_0001, &text[..2]);
+        data.extend(client_frame(0b0000_0000, &text[2..7]));
+        data.extend(client_frame(0b1000_1001, b"ping"));
+        data.extend(client_frame(0b1000_0000, &text[7..]));
+        data.extend(client_frame(0b1000_0010, &[1, 2, 3]));
+
+        let messages = feed_bytewise(&mut WsParser::default(), &data).unwrap();
+        assert_eq!(messages.len(), 3);
+        assert!(matches!(&messages[0], Message::Ping(data) if data == b"ping"));
+        assert!(matches!(&messages[1], Message::Text(text) if text == "héllo wörld"));
+        assert!(matches!(&messages[2], Message::Binary(data) if data == &[1, 2, 3]));
+    }
+
+    #[test]
+    fn whole_buffer_at_once() {
+        let mut data = client_frame(0b0000_0010, &[7; 300]);
+        data.extend(client_frame(0b1000_0000, &[8; 70000]));
+        data.extend(client_frame(0b1000_1000, &[0x03, 0xe8, b'o', b'k']));
+
+        let mut parser = WsParser::default();
+        let (message, rest) = parser.parse(&data).unwrap();
+        assert!(matches!(message, Some(Message::Binary(data)) if data.len() == 70300 && data[299] == 7 && data[300] == 8));
+        let (message, rest) = parser.parse(rest).unwrap();
+        assert!(matches!(message, Some(Message::Close(frame)) if frame == CloseFrame::new(1000, "ok")));
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn compressed_fragments() {
+        let text = "{\"hello\": \"world\"} ".repeat(50);
+        let deflated = deflate(text.as_bytes());
+        let (first, last) = deflated.split_at(deflated.len() / 2);
+        let mut data = client_frame(0b0100_0001, first);
+        data.extend(client_frame(0b1000_0000, last));
+
+        let mut parser = WsParser { inflater: Some(Decompress::new(false)), ..WsParser::default() };
+        let messages = feed_bytewise(&mut parser, &data).unwrap();
+        assert!(matches!(&messages[..], [Message::Text(inflated)] if *inflated == text));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn refuses_before_buffering() {
+        let limits = Limits { frame: 100, message: 150 };
+
+        // the header and mask are enough to tell
+        let mut parser = WsParser { limits, ..WsParser::default() };
+        let data = client_frame(0b1000_0010, &[0; 101]);
+        assert_eq!(feed_bytewise(&mut parser, &data[..6]).err(), Some(WebSocketError::TOO_BIG));
+
+        let mut parser = WsParser { limits, ..WsParser::default() };
+        let mut data = client_frame(0b0000_0010, &[0; 100]);
+        data.extend(client_frame(0b1000_0000, &[0; 51]));
+        assert_eq!(feed_bytewise(&mut parser, &data).err(), Some(WebSocketError::TOO_BIG));
+    }
+
+    #[test]
+    fn refuses_bad_control_frames() {
+        let data = client_frame(0b1000_1001, &[0; 126]);
+        assert_eq!(feed_bytewise(&mut WsParser::default(), &data).err(), Some(WebSocketError::BAD_CONTROL));
+
+        let data = client_frame(0b0000_1010, b"pong");
+        assert_eq!(feed_bytewise(&mut WsParser::default(), &data).err(), Some(WebSocketError::BAD_CONTROL));
+    }
+}